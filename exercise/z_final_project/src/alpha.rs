@@ -0,0 +1,212 @@
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma, Rgba};
+
+use crate::depth::Depth;
+use crate::linear;
+
+/// **Flatten** any transparency onto a solid background colour.
+pub fn flatten(img: DynamicImage, background: Rgba<u8>) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let background = working_colour(depth, background);
+    let mut rgba = depth.working_copy(img);
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel[3];
+        for c in 0..3 {
+            pixel[c] = pixel[c] * alpha + background[c] * (1.0 - alpha);
+        }
+    }
+    depth.restore(rgba, false)
+}
+
+/// **Chroma key**: make pixels close to `key` transparent, e.g. to remove a green screen.
+///
+/// Pixels within `tolerance` (RGB distance, 0-441) of the key become fully transparent, and
+/// alpha ramps back up to fully opaque over the next `softness` of distance.  Distances are
+/// measured between sRGB colours, whatever the depth of the image.
+pub fn chroma_key(img: DynamicImage, key: Rgba<u8>, tolerance: f32, softness: f32) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let mut rgba = depth.working_copy(img);
+    for pixel in rgba.pixels_mut() {
+        let distance = (0..3)
+            .map(|c| {
+                let value = if depth == Depth::Float {
+                    linear::linear_to_srgb(pixel[c])
+                } else {
+                    pixel[c]
+                };
+                (value * 255.0 - key[c] as f32).powi(2)
+            })
+            .sum::<f32>()
+            .sqrt();
+        let keep = if distance <= tolerance {
+            0.0
+        } else if softness <= 0.0 || distance >= tolerance + softness {
+            1.0
+        } else {
+            (distance - tolerance) / softness
+        };
+        pixel[3] *= keep;
+    }
+    depth.restore(rgba, true)
+}
+
+/// **Extract** the alpha channel as a grayscale image (white is opaque).
+pub fn extract_alpha(img: &DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            let mut rgba = img.to_rgba32f();
+            for pixel in rgba.pixels_mut() {
+                *pixel = Rgba([pixel[3], pixel[3], pixel[3], 1.0]);
+            }
+            Depth::Float.restore(rgba, false)
+        }
+        img if Depth::of(img) == Depth::Sixteen => {
+            let rgba = img.to_rgba16();
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+                Luma([rgba.get_pixel(x, y)[3]])
+            }))
+        }
+        img => {
+            let rgba = img.to_rgba8();
+            DynamicImage::ImageLuma8(GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                Luma([rgba.get_pixel(x, y)[3]])
+            }))
+        }
+    }
+}
+
+/// Replace the alpha channel with the brightness of `source` (or of the image itself), so that
+/// white is opaque and black is transparent.  `invert` swaps that around.  `source` must be the
+/// same size as the image.
+pub fn alpha_from_luminance(
+    img: DynamicImage,
+    source: Option<&DynamicImage>,
+    invert: bool,
+) -> Result<DynamicImage, String> {
+    let levels = match source {
+        Some(source) => {
+            same_size(source.dimensions(), img.dimensions())?;
+            brightness(source)
+        }
+        None => brightness(&img),
+    };
+
+    let depth = Depth::of(&img);
+    let mut rgba = depth.working_copy(img);
+    for (pixel, level) in rgba.pixels_mut().zip(levels) {
+        pixel[3] = if invert { 1.0 - level } else { level };
+    }
+    Ok(depth.restore(rgba, true))
+}
+
+/// Check that an alpha image of size `alpha` fits an image of size `image`.
+pub fn same_size(alpha: (u32, u32), image: (u32, u32)) -> Result<(), String> {
+    if alpha == image {
+        Ok(())
+    } else {
+        Err(format!(
+            "the alpha image is {}x{}, but the image is {}x{}",
+            alpha.0, alpha.1, image.0, image.1
+        ))
+    }
+}
+
+/// The sRGB brightness (0.0-1.0) of every pixel, in row order.
+fn brightness(img: &DynamicImage) -> Vec<f32> {
+    match Depth::of(img) {
+        Depth::Eight => img
+            .to_luma8()
+            .pixels()
+            .map(|l| l[0] as f32 / 255.0)
+            .collect(),
+        _ => linear::to_srgb(img.clone(), true)
+            .into_luma16()
+            .pixels()
+            .map(|l| l[0] as f32 / 65535.0)
+            .collect(),
+    }
+}
+
+/// **Premultiply** the colour channels by alpha.
+pub fn premultiply(img: DynamicImage) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let mut rgba = depth.working_copy(img);
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel[3];
+        for c in 0..3 {
+            pixel[c] *= alpha;
+        }
+    }
+    depth.restore(rgba, true)
+}
+
+/// **Unpremultiply**: undo `premultiply` by dividing the colour channels by alpha.
+pub fn unpremultiply(img: DynamicImage) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let mut rgba = depth.working_copy(img);
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel[3];
+        if alpha <= 0.0 {
+            continue;
+        }
+        for c in 0..3 {
+            pixel[c] = (pixel[c] / alpha).min(1.0);
+        }
+    }
+    depth.restore(rgba, true)
+}
+
+/// A colour in the working encoding for `depth` (linear light for float images).
+fn working_colour(depth: Depth, colour: Rgba<u8>) -> [f32; 4] {
+    let [r, g, b, a] = colour.0.map(|c| c as f32 / 255.0);
+    if depth == Depth::Float {
+        let [r, g, b] = [r, g, b].map(linear::srgb_to_linear);
+        [r, g, b, a]
+    } else {
+        [r, g, b, a]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn premultiply_round_trips() {
+        let img = DynamicImage::ImageRgba16(ImageBuffer::from_fn(16, 16, |x, y| {
+            Rgba([
+                x as u16 * 4000,
+                y as u16 * 4000,
+                30000,
+                1000 + (x + y) as u16 * 2000,
+            ])
+        }));
+        let round_trip = unpremultiply(premultiply(img.clone())).into_rgba16();
+        for (before, after) in img.into_rgba16().pixels().zip(round_trip.pixels()) {
+            for c in 0..4 {
+                assert!(
+                    before[c].abs_diff(after[c]) <= 40,
+                    "{before:?} vs {after:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn premultiply_scales_by_alpha() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([200, 100, 50, 128])));
+        let premultiplied = premultiply(img).into_rgba8();
+        assert_eq!(premultiplied.get_pixel(0, 0), &Rgba([100, 50, 25, 128]));
+    }
+
+    #[test]
+    fn alpha_images_must_be_the_same_size() {
+        let img = DynamicImage::new_rgb8(8, 6);
+        let white = DynamicImage::ImageLuma8(GrayImage::from_pixel(8, 6, Luma([255])));
+        let small = DynamicImage::new_luma8(4, 3);
+
+        let opaque = alpha_from_luminance(img.clone(), Some(&white), false).unwrap();
+        assert!(opaque.into_rgba8().pixels().all(|pixel| pixel[3] == 255));
+        assert!(alpha_from_luminance(img, Some(&small), false).is_err());
+    }
+}
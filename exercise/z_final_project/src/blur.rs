@@ -0,0 +1,391 @@
+use std::ops::{AddAssign, SubAssign};
+
+use clap::ValueEnum;
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::depth::Depth;
+
+/// Most samples taken per pixel by the motion and radial blurs.
+const MAX_SAMPLES: usize = 256;
+
+/// Ways of blurring an image.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlurMethod {
+    /// true Gaussian blur; slow for large amounts
+    Gaussian,
+    /// three box blur passes approximating a Gaussian; the same speed for any amount
+    Fast,
+    /// a single box blur, with the amount used as the radius
+    Box,
+}
+
+impl BlurMethod {
+    /// How far away (in pixels) a blur of `amount` can take colours from.
+    pub fn reach(self, amount: f32) -> u32 {
+        match self {
+            // the image crate treats a sigma of zero or less as 1.0, and samples 2 sigma away
+            BlurMethod::Gaussian => {
+                (2.0 * if amount > 0.0 { amount } else { 1.0 }).ceil() as u32 + 1
+            }
+            BlurMethod::Fast if amount > 0.0 => {
+                box_radii_for_gaussian(amount, 3).iter().sum::<usize>() as u32
+            }
+            BlurMethod::Fast => 0,
+            BlurMethod::Box => amount.round().max(0.0) as u32,
+        }
+    }
+}
+
+/// Ways of blurring outwards from (or around) a centre point.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RadialKind {
+    /// streak towards the centre, as if zooming the camera
+    Zoom,
+    /// streak around the centre, as if spinning the camera
+    Spin,
+}
+
+/// Approximate a Gaussian blur with standard deviation `sigma` using three box blurs.
+///
+/// Each box blur uses a running sum, so the cost per pixel doesn't depend on `sigma`.
+pub fn fast_gaussian(img: DynamicImage, sigma: f32) -> DynamicImage {
+    if sigma <= 0.0 {
+        return img;
+    }
+    box_blur_passes(img, &box_radii_for_gaussian(sigma, 3))
+}
+
+/// **Box blur**: replace each pixel with the plain average of the square around it.
+pub fn box_blur(img: DynamicImage, radius: u32) -> DynamicImage {
+    if radius == 0 {
+        return img;
+    }
+    box_blur_passes(img, &[radius as usize])
+}
+
+/// **Motion blur**: smear the image `length` pixels along the direction `angle` (in degrees,
+/// anticlockwise from pointing right).
+pub fn motion_blur(img: DynamicImage, length: f32, angle: f32) -> DynamicImage {
+    if length <= 1.0 {
+        return img;
+    }
+    let (dy, dx) = (-angle.to_radians()).sin_cos();
+    let samples = (length.ceil() as usize).clamp(2, MAX_SAMPLES);
+
+    map_rgba(img, |source, x, y| {
+        let (x, y) = (x as f32, y as f32);
+        average_samples(source, samples, |t| {
+            let offset = (t - 0.5) * length;
+            (x + dx * offset, y + dy * offset)
+        })
+    })
+}
+
+/// **Radial blur** around a centre given as fractions of the width and height.
+///
+/// For a zoom blur `amount` is how far (0.0-1.0) each pixel streaks towards the centre.  For a
+/// spin blur it is the angle in degrees the image is swept through.
+pub fn radial_blur(
+    img: DynamicImage,
+    kind: RadialKind,
+    amount: f32,
+    centre: (f32, f32),
+) -> DynamicImage {
+    if amount <= 0.0 {
+        return img;
+    }
+    let cx = centre.0 * img.width() as f32;
+    let cy = centre.1 * img.height() as f32;
+
+    map_rgba(img, |source, x, y| {
+        let (rx, ry) = (x as f32 - cx, y as f32 - cy);
+        let distance = (rx * rx + ry * ry).sqrt();
+        match kind {
+            RadialKind::Zoom => {
+                let amount = amount.min(1.0);
+                let samples = ((distance * amount).ceil() as usize).clamp(1, MAX_SAMPLES);
+                average_samples(source, samples, |t| {
+                    let scale = 1.0 - amount * t;
+                    (cx + rx * scale, cy + ry * scale)
+                })
+            }
+            RadialKind::Spin => {
+                let sweep = amount.to_radians();
+                let samples = ((distance * sweep).ceil() as usize).clamp(1, MAX_SAMPLES);
+                average_samples(source, samples, |t| {
+                    let (sin, cos) = ((t - 0.5) * sweep).sin_cos();
+                    (cx + rx * cos - ry * sin, cy + rx * sin + ry * cos)
+                })
+            }
+        }
+    })
+}
+
+/// Box sizes (as radii) for `passes` box blurs that together approximate a Gaussian with
+/// standard deviation `sigma`.  See Kutskir, "Fastest Gaussian Blur (in linear time)".
+fn box_radii_for_gaussian(sigma: f32, passes: usize) -> Vec<usize> {
+    let n = passes as f32;
+    let ideal_width = (12.0 * sigma * sigma / n + 1.0).sqrt();
+    let mut lower = ideal_width.floor() as i64;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let lower = lower.max(1);
+    let upper = lower + 2;
+
+    let lower_f = lower as f32;
+    let ideal_count = (12.0 * sigma * sigma - n * lower_f * lower_f - 4.0 * n * lower_f - 3.0 * n)
+        / (-4.0 * lower_f - 4.0);
+    let count = ideal_count.round().max(0.0) as usize;
+
+    (0..passes)
+        .map(|i| if i < count { lower } else { upper })
+        .map(|width| (width as usize - 1) / 2)
+        .collect()
+}
+
+/// Channel types the box blur can work on directly, with the type their running sums are
+/// kept in.
+trait Channel: Copy {
+    type Sum: Copy + AddAssign + SubAssign + std::iter::Sum;
+    fn widen(self) -> Self::Sum;
+    fn average(sum: Self::Sum, count: usize) -> Self;
+}
+
+impl Channel for u8 {
+    type Sum = u32;
+    fn widen(self) -> u32 {
+        self as u32
+    }
+    fn average(sum: u32, count: usize) -> Self {
+        ((sum + count as u32 / 2) / count as u32) as u8
+    }
+}
+
+impl Channel for u16 {
+    type Sum = u64;
+    fn widen(self) -> u64 {
+        self as u64
+    }
+    fn average(sum: u64, count: usize) -> Self {
+        ((sum + count as u64 / 2) / count as u64) as u16
+    }
+}
+
+impl Channel for f32 {
+    type Sum = f64;
+    fn widen(self) -> f64 {
+        self as f64
+    }
+    fn average(sum: f64, count: usize) -> Self {
+        (sum / count as f64) as f32
+    }
+}
+
+/// Run a box blur pass of each of the given radii over the raw interleaved channels of the
+/// image, keeping its colour type and bit depth.
+fn box_blur_passes(img: DynamicImage, radii: &[usize]) -> DynamicImage {
+    fn run<T: Channel>(data: &mut [T], size: (u32, u32), channels: usize, radii: &[usize]) {
+        let (width, height) = (size.0 as usize, size.1 as usize);
+        for &radius in radii {
+            box_blur_raw(data, width, height, channels, radius);
+        }
+    }
+
+    let size = (img.width(), img.height());
+    match img {
+        DynamicImage::ImageLuma8(mut buffer) => {
+            run(&mut buffer, size, 1, radii);
+            DynamicImage::ImageLuma8(buffer)
+        }
+        DynamicImage::ImageLumaA8(mut buffer) => {
+            run(&mut buffer, size, 2, radii);
+            DynamicImage::ImageLumaA8(buffer)
+        }
+        DynamicImage::ImageRgb8(mut buffer) => {
+            run(&mut buffer, size, 3, radii);
+            DynamicImage::ImageRgb8(buffer)
+        }
+        DynamicImage::ImageRgba8(mut buffer) => {
+            run(&mut buffer, size, 4, radii);
+            DynamicImage::ImageRgba8(buffer)
+        }
+        DynamicImage::ImageLuma16(mut buffer) => {
+            run(&mut buffer, size, 1, radii);
+            DynamicImage::ImageLuma16(buffer)
+        }
+        DynamicImage::ImageLumaA16(mut buffer) => {
+            run(&mut buffer, size, 2, radii);
+            DynamicImage::ImageLumaA16(buffer)
+        }
+        DynamicImage::ImageRgb16(mut buffer) => {
+            run(&mut buffer, size, 3, radii);
+            DynamicImage::ImageRgb16(buffer)
+        }
+        DynamicImage::ImageRgba16(mut buffer) => {
+            run(&mut buffer, size, 4, radii);
+            DynamicImage::ImageRgba16(buffer)
+        }
+        DynamicImage::ImageRgb32F(mut buffer) => {
+            run(&mut buffer, size, 3, radii);
+            DynamicImage::ImageRgb32F(buffer)
+        }
+        img => {
+            let mut buffer = img.into_rgba32f();
+            run(&mut buffer, size, 4, radii);
+            DynamicImage::ImageRgba32F(buffer)
+        }
+    }
+}
+
+/// One horizontal and one vertical box blur pass of the given radius, using running sums.
+fn box_blur_raw<T: Channel>(
+    data: &mut [T],
+    width: usize,
+    height: usize,
+    channels: usize,
+    radius: usize,
+) {
+    if width == 0 || height == 0 {
+        return;
+    }
+    let count = 2 * radius + 1;
+    let stride = width * channels;
+    let mut scratch = data.to_vec();
+
+    // horizontal pass: data -> scratch
+    for y in 0..height {
+        let row = &data[y * stride..(y + 1) * stride];
+        let out = &mut scratch[y * stride..(y + 1) * stride];
+        for c in 0..channels {
+            let at = |x: usize| row[x.min(width - 1) * channels + c].widen();
+            let mut sum: T::Sum = (0..count).map(|i| at(i.saturating_sub(radius))).sum();
+            for x in 0..width {
+                out[x * channels + c] = T::average(sum, count);
+                sum += at(x + radius + 1);
+                sum -= at(x.saturating_sub(radius));
+            }
+        }
+    }
+
+    // vertical pass: scratch -> data, a whole row at a time to stay cache friendly
+    let row = |y: usize| &scratch[y.min(height - 1) * stride..(y.min(height - 1) + 1) * stride];
+    let mut sums: Vec<T::Sum> = row(0).iter().map(|&v| v.widen()).collect();
+    for dy in 1..count {
+        for (sum, &v) in sums.iter_mut().zip(row(dy.saturating_sub(radius))) {
+            *sum += v.widen();
+        }
+    }
+    for y in 0..height {
+        let out = &mut data[y * stride..(y + 1) * stride];
+        for (value, &sum) in out.iter_mut().zip(&sums) {
+            *value = T::average(sum, count);
+        }
+        let (incoming, outgoing) = (row(y + radius + 1), row(y.saturating_sub(radius)));
+        for ((sum, &add), &remove) in sums.iter_mut().zip(incoming).zip(outgoing) {
+            *sum += add.widen();
+            *sum -= remove.widen();
+        }
+    }
+}
+
+/// Build a new image by calling `f` for every pixel with the original image to sample from.
+///
+/// The image is worked on as floats in its own encoding and keeps its bit depth.
+fn map_rgba(img: DynamicImage, f: impl Fn(&Rgba32FImage, u32, u32) -> Rgba<f32>) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let source = depth.working_copy(img);
+    let out = Rgba32FImage::from_fn(source.width(), source.height(), |x, y| f(&source, x, y));
+    depth.restore(out, had_alpha)
+}
+
+/// Average `samples` bilinear samples at the positions `position(t)` for t spread over 0.0-1.0.
+fn average_samples(
+    source: &Rgba32FImage,
+    samples: usize,
+    position: impl Fn(f32) -> (f32, f32),
+) -> Rgba<f32> {
+    let mut sum = [0.0f32; 4];
+    for i in 0..samples {
+        let t = if samples == 1 {
+            0.0
+        } else {
+            i as f32 / (samples - 1) as f32
+        };
+        let (x, y) = position(t);
+        let sample = bilinear(source, x, y);
+        for c in 0..4 {
+            sum[c] += sample[c];
+        }
+    }
+    Rgba(sum.map(|s| s / samples as f32))
+}
+
+/// Sample the image between pixels, clamping at the edges.
+fn bilinear(source: &Rgba32FImage, x: f32, y: f32) -> [f32; 4] {
+    let max_x = source.width() as f32 - 1.0;
+    let max_y = source.height() as f32 - 1.0;
+    let (x, y) = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let x1 = (x0 + 1).min(source.width() - 1);
+    let y1 = (y0 + 1).min(source.height() - 1);
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+    let p00 = source.get_pixel(x0, y0);
+    let p10 = source.get_pixel(x1, y0);
+    let p01 = source.get_pixel(x0, y1);
+    let p11 = source.get_pixel(x1, y1);
+    [0, 1, 2, 3].map(|c| {
+        let top = p00[c] * (1.0 - fx) + p10[c] * fx;
+        let bottom = p01[c] * (1.0 - fx) + p11[c] * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    #[test]
+    fn box_radii_match_the_gaussian() {
+        for sigma in [2.0f32, 3.5, 5.0, 10.0, 30.0] {
+            // a box of width w has variance (w² - 1) / 12, and variances add; with only odd
+            // widths to choose from, small sigmas can be off by a few percent
+            let variance: f32 = box_radii_for_gaussian(sigma, 3)
+                .iter()
+                .map(|&r| {
+                    let width = (2 * r + 1) as f32;
+                    (width * width - 1.0) / 12.0
+                })
+                .sum();
+            let error = (variance.sqrt() - sigma).abs() / sigma;
+            assert!(error < 0.1, "sigma {sigma} gave {}", variance.sqrt());
+        }
+    }
+
+    #[test]
+    fn box_blur_spreads_an_impulse_evenly() {
+        let impulse = ImageBuffer::from_fn(9, 9, |x, y| {
+            let value = if (x, y) == (4, 4) { 81.0 } else { 0.0 };
+            Rgb([value; 3])
+        });
+        let blurred = box_blur(DynamicImage::ImageRgb32F(impulse), 1).into_rgb32f();
+        for (x, y, pixel) in blurred.enumerate_pixels() {
+            let expected = if x.abs_diff(4) <= 1 && y.abs_diff(4) <= 1 {
+                9.0
+            } else {
+                0.0
+            };
+            assert!((pixel[0] - expected).abs() < 1e-4, "{x},{y}: {}", pixel[0]);
+        }
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(20, 12, Rgb([30, 140, 250])));
+        assert_eq!(fast_gaussian(flat.clone(), 4.0), flat);
+        assert_eq!(box_blur(flat.clone(), 3), flat);
+    }
+}
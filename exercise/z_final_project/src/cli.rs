@@ -0,0 +1,2424 @@
+//! The `mirage` command line.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::error::ErrorKind;
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use image::{ColorType, DynamicImage, ImageFormat, Rgba};
+
+use crate::blur::{self, BlurMethod, RadialKind};
+use crate::colour::parse_colour;
+use crate::colour_matrix::{self, parse_filter, ColourMatrix};
+use crate::denoise::{self, DenoiseMethod};
+use crate::depth::Depth;
+use crate::dither::{self, DitherMethod};
+use crate::gravity::Gravity;
+use crate::hash::{self, HashAlgorithm};
+use crate::mask::{self, parse_region, Region};
+use crate::montage::{self, parse_size, MontageLayout};
+use crate::overlay::{self, BlendMode};
+use crate::palette::{self, PaletteMethod};
+use crate::progress::{self, Progress, Timings};
+use crate::recipe::Recipe;
+use crate::registry::{Action, ImageOp, Registry, Shape, Step};
+use crate::seamless::{self, SeamlessMethod};
+use crate::session::{self, Session};
+use crate::shell::{self, History};
+use crate::text::{self, TextStyle};
+use crate::texture::{self, NoiseKind, NoiseSettings, StripeDirection};
+use crate::watch::{self, Outputs, Watcher};
+use crate::{alpha, compare, linear, output, preview, serve, tiled};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)] // Read from `Cargo.toml`
+struct Args {
+    /// tool to run; operations come from the `Registry` and are left as `None`
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// input image file (required by operations)
+    // #[arg(value_name = "INPUT_FILE")]
+    infile: Option<String>,
+    /// output image file (required by operations)
+    // #[arg(value_name = "OUTPUT_FILE")]
+    outfile: Option<String>,
+    /// show the result of the operation in the terminal before saving it
+    #[arg(long, global = true)]
+    preview: bool,
+    /// only change this part of the image, given as x,y,width,height
+    #[arg(long, global = true, value_parser = parse_region, conflicts_with = "mask")]
+    region: Option<Region>,
+    /// only change the light parts of this mask image (stretched to fit the image)
+    #[arg(long, global = true, value_name = "MASK_FILE")]
+    mask: Option<String>,
+    /// soften the edge of the region or mask over about this many pixels
+    #[arg(long, global = true, default_value_t = 0.0)]
+    feather: f32,
+    /// work in linear light, so blurs and blends mix colours the way light does
+    #[arg(long, global = true)]
+    linear: bool,
+    /// stream a PNG through the operation in strips, for images too big to fit in memory;
+    /// works with invert, brighten, grayscale, blur, denoise and crop
+    #[arg(
+        long,
+        global = true,
+        conflicts_with_all = ["preview", "region", "mask", "linear"]
+    )]
+    tiled: bool,
+    /// rows in each strip for --tiled
+    #[arg(long, global = true, default_value_t = 256)]
+    tile_rows: u32,
+    /// show a progress bar for long jobs, e.g. fractal, montage or dupes
+    #[arg(long, global = true)]
+    progress: bool,
+    /// report how long decoding, each operation and encoding took
+    #[arg(long, global = true)]
+    timings: bool,
+    /// check the arguments, the input and the output format, and show the size and colour type
+    /// after each step, without writing anything
+    #[arg(long, global = true, conflicts_with_all = ["preview", "timings"])]
+    dry_run: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Time every operation on an image and print a table of the results
+    Bench {
+        /// reference image to run the operations on
+        #[arg(value_name = "INPUT_FILE")]
+        infile: String,
+        /// times to run each operation
+        #[arg(long, default_value_t = 5)]
+        runs: usize,
+    },
+    /// Compare two images and report MSE, PSNR and SSIM
+    ///
+    /// Images are compared as RGBA at full precision, so alpha and 16-bit differences count.
+    /// Exits with status 1 if any of the given thresholds are not met, or 2 if the images can't
+    /// be compared at all (e.g. they are different sizes).
+    Compare {
+        /// first image, e.g. the expected "golden" image
+        #[arg(value_name = "IMAGE_A")]
+        a: String,
+        /// second image, e.g. the freshly rendered image
+        #[arg(value_name = "IMAGE_B")]
+        b: String,
+        /// fail if the mean squared error is above this
+        #[arg(long)]
+        max_mse: Option<f64>,
+        /// fail if the PSNR (in dB) is below this
+        #[arg(long)]
+        min_psnr: Option<f64>,
+        /// fail if the SSIM is below this (0.0-1.0)
+        #[arg(long)]
+        min_ssim: Option<f64>,
+        /// write an image highlighting the differences to this file
+        #[arg(long, value_name = "DIFF_FILE")]
+        diff: Option<String>,
+        /// ignore per-channel differences up to this amount in the diff image (0-255)
+        #[arg(long, default_value_t = 0)]
+        fuzz: u8,
+    },
+    /// Print perceptual hashes of images
+    Hash {
+        /// images to hash
+        #[arg(value_name = "FILES", required = true)]
+        files: Vec<String>,
+        /// only print this kind of hash (defaults to all of them)
+        #[arg(long, value_enum)]
+        algorithm: Option<HashAlgorithm>,
+    },
+    /// Find groups of visually similar images in a directory
+    Dupes {
+        /// directory to search
+        #[arg(value_name = "DIR")]
+        dir: String,
+        /// kind of hash to compare images with
+        #[arg(long, value_enum, default_value_t = HashAlgorithm::Phash)]
+        algorithm: HashAlgorithm,
+        /// maximum number of differing hash bits (out of 64) for images to count as similar
+        #[arg(long, default_value_t = 10)]
+        threshold: u32,
+        /// search subdirectories too
+        #[arg(long, short)]
+        recursive: bool,
+    },
+    /// Arrange many images in a grid on a single contact sheet
+    Montage {
+        /// images to include; quote glob patterns like 'shots/*.png'
+        #[arg(value_name = "FILES", required = true)]
+        patterns: Vec<String>,
+        /// contact sheet image file to write
+        #[arg(value_name = "OUTPUT_FILE")]
+        outfile: String,
+        /// number of tiles per row
+        #[arg(long, alias = "columns", default_value_t = 6)]
+        cols: u32,
+        /// size of each tile, e.g. 200x200
+        #[arg(long, default_value = "200x200", value_parser = parse_size)]
+        tile: (u32, u32),
+        /// space between tiles in pixels
+        #[arg(long, default_value_t = 8)]
+        gap: u32,
+        /// write each file name below its tile
+        #[arg(long)]
+        labels: bool,
+        /// background colour
+        #[arg(long, default_value = "white", value_parser = parse_colour)]
+        background: Rgba<u8>,
+    },
+    /// Report the dominant colours of an image
+    Palette {
+        /// image to take the colours from
+        #[arg(value_name = "INPUT_FILE")]
+        infile: String,
+        /// number of colours to find
+        #[arg(long, alias = "colours", default_value_t = 8)]
+        colors: usize,
+        /// how to choose the colours
+        #[arg(long, value_enum, default_value_t = PaletteMethod::Kmeans)]
+        method: PaletteMethod,
+        /// print the palette as JSON instead of text
+        #[arg(long)]
+        json: bool,
+        /// also draw the palette as a strip of swatches to this file
+        #[arg(long, value_name = "SWATCH_FILE")]
+        swatch: Option<String>,
+    },
+    /// Process images over HTTP: POST an image to /process with the operations in the query
+    /// string, e.g. `/process?op=blur+2&op=rotate+left&format=jpg`
+    ///
+    /// Operations can also be given as a JSON list, `?ops=["blur 2","rotate left"]`.  Operations
+    /// that read other files from the server (overlay, fonts, palette files) are refused, as are
+    /// very large blurs, denoise radii and text, and any request that would make an image
+    /// bigger than --max-pixels.
+    Serve {
+        /// port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// address to listen on; 0.0.0.0 accepts connections from other machines
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+        /// requests processed at once; any more wait their turn
+        #[arg(long, default_value_t = 4)]
+        workers: usize,
+        /// largest upload accepted
+        #[arg(long, value_name = "MEGABYTES", default_value_t = 25)]
+        max_upload: u64,
+        /// largest image accepted or made by the operations
+        #[arg(long, value_name = "MEGAPIXELS", default_value_t = 50)]
+        max_pixels: u64,
+    },
+    /// Keep a list of operations to apply to an image in a session file, without ever
+    /// changing the original
+    Session {
+        /// session file
+        #[arg(long, default_value = session::DEFAULT_FILE)]
+        file: String,
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+    /// Load an image and apply operations to it interactively, one line at a time
+    Shell {
+        /// image to work on
+        #[arg(value_name = "INPUT_FILE")]
+        infile: String,
+    },
+    /// Show an image in the terminal using 24-bit colour (or ASCII art)
+    View {
+        /// image to show
+        #[arg(value_name = "INPUT_FILE")]
+        infile: String,
+        /// width in characters (defaults to $COLUMNS, or 80)
+        #[arg(long)]
+        width: Option<u32>,
+        /// draw plain ASCII art instead, for terminals without colour
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Watch a folder and apply a recipe to every image that is added or changed
+    ///
+    /// Images are processed once they stop changing, and again whenever they change.  Images
+    /// whose result is newer than they are are left alone, so restarting doesn't redo them.
+    /// Recipes with very large blurs, denoise radii or text are refused, as by serve.
+    // the example is kept out of the doc comment, where rustdoc would take it for Rust
+    #[command(after_long_help = WATCH_RECIPE_HELP)]
+    Watch {
+        /// folder to watch
+        #[arg(value_name = "DIR")]
+        dir: String,
+        /// folder to write the results to
+        #[arg(long, value_name = "OUT_DIR")]
+        out: String,
+        /// TOML file with the operations to apply
+        #[arg(long, value_name = "RECIPE_FILE")]
+        recipe: String,
+        /// seconds between looks at the folder
+        #[arg(long, value_name = "SECONDS", default_value = "2", value_parser = watch::parse_interval)]
+        interval: Duration,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionAction {
+    /// Start a new session for an image
+    New {
+        /// the original image
+        #[arg(value_name = "INPUT_FILE")]
+        infile: String,
+        /// replace an existing session file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Add an operation to the end, e.g. `session add blur 2`
+    Add {
+        /// the operation and its arguments, as they would be given to mirage
+        #[arg(
+            value_name = "OPERATION",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        words: Vec<String>,
+    },
+    /// List the operations in order
+    List,
+    /// Take the last operation off (redo puts it back)
+    Undo,
+    /// Put back the operation most recently taken off by undo
+    Redo,
+    /// Remove an operation from the list
+    Remove {
+        /// position of the operation, as shown by list
+        #[arg(value_name = "POSITION")]
+        position: usize,
+    },
+    /// Apply the operations to the original image and save the result
+    Render {
+        /// image file to write; must not be the original
+        #[arg(value_name = "OUTPUT_FILE")]
+        outfile: String,
+    },
+}
+
+/// The recipe format, for `mirage watch --help`.
+const WATCH_RECIPE_HELP: &str = "\
+A recipe is a TOML file with a list of operations, written as they would be on the command line, \
+and optionally the format to write:
+
+    operations = [\"blur 1\", \"text 'Proof' --gravity south-east\"]
+    format = \"jpg\"";
+
+/// Operations and commands understood by `mirage shell`
+#[derive(Parser)]
+#[command(name = "", no_binary_name = true, disable_version_flag = true)]
+struct ShellLine {
+    /// command to run; operations come from the `Registry` and are left as `None`
+    #[command(subcommand)]
+    command: Option<ShellCommand>,
+}
+
+#[derive(Subcommand)]
+enum ShellCommand {
+    /// Go back to before the last operation
+    Undo,
+    /// Save the image
+    Save {
+        /// image file to write
+        #[arg(value_name = "OUTPUT_FILE")]
+        outfile: String,
+    },
+    /// Show the size and colour type of the image
+    Info,
+    /// Show the image in the terminal
+    View {
+        /// width in characters (defaults to $COLUMNS, or 80)
+        #[arg(long)]
+        width: Option<u32>,
+        /// draw plain ASCII art instead, for terminals without colour
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Leave the shell
+    #[command(alias = "exit")]
+    Quit,
+}
+
+#[derive(Subcommand, Clone)]
+enum Operation {
+    /// Blur the image
+    Blur {
+        /// amount to blur by
+        #[arg(value_name = "BLUR_AMOUNT")]
+        blur_amount: f32,
+        /// how to blur; `fast` is much quicker than `gaussian` for large amounts
+        #[arg(long, value_enum, default_value_t = BlurMethod::Gaussian)]
+        method: BlurMethod,
+    },
+    /// Smear the image in one direction, as if the camera moved
+    MotionBlur {
+        /// length of the smear in pixels
+        #[arg(value_name = "LENGTH")]
+        length: f32,
+        /// direction of the smear in degrees, anticlockwise from horizontal
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+        angle: f32,
+    },
+    /// Blur towards or around a centre point
+    RadialBlur {
+        /// zoom: how far pixels streak towards the centre (0.0-1.0); spin: angle in degrees
+        #[arg(value_name = "AMOUNT")]
+        amount: f32,
+        /// zoom towards the centre or spin around it
+        #[arg(long, value_enum, default_value_t = RadialKind::Zoom)]
+        kind: RadialKind,
+        /// horizontal position of the centre, as a fraction of the width
+        #[arg(long, default_value_t = 0.5)]
+        centre_x: f32,
+        /// vertical position of the centre, as a fraction of the height
+        #[arg(long, default_value_t = 0.5)]
+        centre_y: f32,
+    },
+    /// Remove noise without blurring edges, e.g. from scanned documents or photos
+    Denoise {
+        /// denoising algorithm
+        #[arg(value_name = "METHOD", value_enum)]
+        method: DenoiseMethod,
+        /// pixels around each pixel to look at [default: 1 for median, 3 for bilateral and 5
+        /// for nl-means]
+        #[arg(long)]
+        radius: Option<u32>,
+        /// bilateral and nl-means: how different colours can be and still be mixed, as a
+        /// fraction of the full range (0.0-1.0)
+        #[arg(long, default_value_t = 0.05)]
+        strength: f32,
+    },
+    /// Make the image brighter
+    Brighten {
+        /// amount to brighten by
+        #[arg(value_name = "BRIGHTEN_AMOUNT")]
+        brighten_amount: i32,
+    },
+    /// Crop the image
+    Crop {
+        /// x position to crop image from
+        #[arg(value_name = "CROP_X")]
+        x: u32,
+        /// y position to crop image from
+        #[arg(value_name = "CROP_Y")]
+        y: u32,
+        /// width to crop image to
+        #[arg(value_name = "CROP_WIDTH")]
+        width: u32,
+        /// height to crop image to
+        #[arg(value_name = "CROP_HEIGHT")]
+        height: u32,
+    },
+    /// Rotate the image
+    Rotate {
+        /// amount to rotate by
+        #[arg(value_name = "ROTATE_AMOUNT")]
+        rotate_amount: RotateAmount,
+    },
+    /// Invert the image
+    Invert,
+    /// Remove colour from the image
+    Grayscale,
+    /// Generate a fun image
+    Generate {
+        /// Amount of Red in generated colour swatch (0-255)
+        #[arg(value_name = "RED_AMOUNT")]
+        red_amount: u8,
+        /// Amount of Green in generated colour swatch (0-255)
+        #[arg(value_name = "GREEN_AMOUNT")]
+        green_amount: u8,
+        /// Amount of Blue in generated colour swatch (0-255)
+        #[arg(value_name = "BLUE_AMOUNT")]
+        blue_amount: u8,
+    },
+    /// Generate a fractal
+    Fractal,
+    /// Generate grayscale noise that tiles seamlessly, e.g. for game textures
+    Noise {
+        /// kind of noise
+        #[arg(value_name = "KIND", value_enum)]
+        kind: NoiseKind,
+        /// size of the texture, e.g. 512x512; at most 16384 each way
+        #[arg(long, default_value = "512x512", value_parser = texture::parse_texture_size)]
+        size: (u32, u32),
+        /// features across the width in the first octave
+        #[arg(long, default_value_t = 8)]
+        cells: u32,
+        /// layers of finer and finer detail
+        #[arg(long, default_value_t = 4)]
+        octaves: u32,
+        /// how much each octave counts compared to the one before (0.0-1.0)
+        #[arg(long, default_value_t = 0.5)]
+        persistence: f32,
+        /// the same seed always gives the same texture
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Generate a checkerboard; an even number of squares tiles seamlessly
+    Checkerboard {
+        /// size of the texture, e.g. 512x512; at most 16384 each way
+        #[arg(long, default_value = "512x512", value_parser = texture::parse_texture_size)]
+        size: (u32, u32),
+        /// squares across the width; the number down keeps them square
+        #[arg(long, default_value_t = 8)]
+        squares: u32,
+        /// colour of the first square and every other one
+        #[arg(long, alias = "color", default_value = "black", value_parser = parse_colour)]
+        colour: Rgba<u8>,
+        /// colour of the rest of the squares
+        #[arg(long, default_value = "white", value_parser = parse_colour)]
+        background: Rgba<u8>,
+    },
+    /// Generate stripes that tile seamlessly
+    Stripes {
+        /// size of the texture, e.g. 512x512; at most 16384 each way
+        #[arg(long, default_value = "512x512", value_parser = texture::parse_texture_size)]
+        size: (u32, u32),
+        /// stripes of each colour across the image
+        #[arg(long, default_value_t = 8)]
+        stripes: u32,
+        /// direction the stripes run in
+        #[arg(long, value_enum, default_value_t = StripeDirection::Horizontal)]
+        direction: StripeDirection,
+        /// 0.0 for hard edges up to 1.0 for a smooth wave between the colours
+        #[arg(long, default_value_t = 0.0)]
+        softness: f32,
+        /// colour of the stripes
+        #[arg(long, alias = "color", default_value = "black", value_parser = parse_colour)]
+        colour: Rgba<u8>,
+        /// colour between the stripes
+        #[arg(long, default_value = "white", value_parser = parse_colour)]
+        background: Rgba<u8>,
+    },
+    /// Generate a colourful plasma that tiles seamlessly
+    Plasma {
+        /// size of the texture, e.g. 512x512; at most 16384 each way
+        #[arg(long, default_value = "512x512", value_parser = texture::parse_texture_size)]
+        size: (u32, u32),
+        /// the same seed always gives the same plasma
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Make the image tile seamlessly, e.g. a photo to use as a texture
+    Seamless {
+        /// blend keeps the size; mirror doubles it
+        #[arg(long, value_enum, default_value_t = SeamlessMethod::Blend)]
+        method: SeamlessMethod,
+        /// for blend: how far in from the edges the image fades into a shifted copy, as a
+        /// fraction of its size (0.0-1.0)
+        #[arg(long, default_value_t = 0.5)]
+        overlap: f32,
+    },
+    /// Repeat the image in a grid (3x3 by default) to check how it tiles
+    TilePreview {
+        /// copies across and down, up to 16
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=16))]
+        count: u32,
+    },
+    /// Overlay another image (e.g. a watermark) on top of the image
+    Overlay {
+        /// image to place on top, e.g. a logo PNG with transparency
+        #[arg(value_name = "OVERLAY_FILE")]
+        overlay_file: String,
+        /// where to place the overlay
+        #[arg(long, value_enum, default_value_t = Gravity::Center)]
+        gravity: Gravity,
+        /// horizontal offset inwards from the gravity edge, in pixels
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        x_offset: i64,
+        /// vertical offset inwards from the gravity edge, in pixels
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        y_offset: i64,
+        /// factor to resize the overlay by before placing it
+        #[arg(long, default_value_t = 1.0)]
+        scale: f32,
+        /// opacity of the overlay (0.0-1.0)
+        #[arg(long, default_value_t = 1.0)]
+        opacity: f32,
+        /// how to mix the overlay colours with the image
+        #[arg(long, value_enum, default_value_t = BlendMode::Normal)]
+        blend: BlendMode,
+    },
+    /// Reduce the image to a small number of colours
+    ///
+    /// PNG output is written as an indexed (palette) PNG.
+    Quantize {
+        /// number of colours to keep (1-256)
+        #[arg(value_name = "COLOURS")]
+        colors: usize,
+        /// how to choose the colours
+        #[arg(long, value_enum, default_value_t = PaletteMethod::Kmeans)]
+        method: PaletteMethod,
+    },
+    /// Run the image through a chain of colour filters, each one a colour matrix
+    #[command(alias = "color-matrix")]
+    ColourMatrix {
+        /// filters to apply in order: grayscale, invert, sepia[:AMOUNT], duotone:DARK:LIGHT,
+        /// cross-process, mixer:RR,RG,RB:GR,GG,GB:BR,BG,BB, or a 4x5 matrix as 20 comma
+        /// separated numbers
+        #[arg(
+            value_name = "FILTERS",
+            required = true,
+            value_parser = parse_filter,
+            allow_hyphen_values = true
+        )]
+        filters: Vec<ColourMatrix>,
+    },
+    /// Dither the image to a fixed palette, e.g. black and white for e-ink displays
+    ///
+    /// PNG output is written as an indexed PNG; the `bw` palette gives a 1-bit PNG.
+    Dither {
+        /// dithering algorithm
+        #[arg(value_name = "METHOD", value_enum)]
+        method: DitherMethod,
+        /// bw, gray4, gameboy, cga, or a palette file (image, .gpl or list of hex colours)
+        #[arg(long, default_value = "bw")]
+        palette: String,
+    },
+    /// Flatten any transparency onto a background colour
+    Flatten {
+        /// background colour
+        #[arg(long, default_value = "white", value_parser = parse_colour)]
+        background: Rgba<u8>,
+    },
+    /// Make a background colour transparent, e.g. a green screen or white backdrop
+    ChromaKey {
+        /// colour to remove, e.g. green or #00b140
+        #[arg(value_name = "COLOUR", value_parser = parse_colour)]
+        key: Rgba<u8>,
+        /// colours within this RGB distance of the key become fully transparent
+        #[arg(long, default_value_t = 60.0)]
+        tolerance: f32,
+        /// distance beyond the tolerance over which pixels fade back in, for soft edges
+        #[arg(long, default_value_t = 40.0)]
+        softness: f32,
+    },
+    /// Replace the image with its alpha channel as a grayscale image
+    ExtractAlpha,
+    /// Set the alpha channel from the brightness of an image (white is opaque)
+    AlphaFromLuma {
+        /// image of the same size to take the brightness from (defaults to the image itself)
+        #[arg(value_name = "ALPHA_FILE")]
+        alpha_file: Option<String>,
+        /// make dark areas opaque and light areas transparent instead
+        #[arg(long)]
+        invert: bool,
+    },
+    /// Multiply the colour channels by alpha
+    Premultiply,
+    /// Divide the colour channels by alpha, undoing premultiply
+    Unpremultiply,
+    /// Draw text (a caption, a "DRAFT" stamp...) on the image
+    Text {
+        /// text to draw; use a newline for multiple lines
+        #[arg(value_name = "TEXT")]
+        text: String,
+        /// TrueType/OpenType font file (defaults to the bundled DejaVu Sans Bold)
+        #[arg(long, value_name = "FONT_FILE")]
+        font: Option<String>,
+        /// font size in pixels
+        #[arg(long, default_value_t = 32.0)]
+        size: f32,
+        /// text colour, e.g. white or #ff000080
+        #[arg(long, alias = "color", default_value = "white", value_parser = parse_colour)]
+        colour: Rgba<u8>,
+        /// where to place the text
+        #[arg(long, value_enum, default_value_t = Gravity::Center)]
+        gravity: Gravity,
+        /// horizontal offset inwards from the gravity edge, in pixels
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        x_offset: i64,
+        /// vertical offset inwards from the gravity edge, in pixels
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        y_offset: i64,
+        /// width of the outline around the letters in pixels (0 for none)
+        #[arg(long, default_value_t = 0.0)]
+        outline_width: f32,
+        /// outline colour
+        #[arg(long, alias = "outline-color", default_value = "black", value_parser = parse_colour)]
+        outline_colour: Rgba<u8>,
+        /// horizontal drop shadow offset in pixels (0 for none)
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        shadow_x: i64,
+        /// vertical drop shadow offset in pixels (0 for none)
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        shadow_y: i64,
+        /// drop shadow colour
+        #[arg(long, alias = "shadow-color", default_value = "#00000080", value_parser = parse_colour)]
+        shadow_colour: Rgba<u8>,
+    },
+}
+
+impl Operation {
+    /// Whether the operation reduces the image to a palette of 8-bit sRGB colours.  Every other
+    /// operation keeps the bit depth of the image, working in linear light on float images.
+    fn reduces_colours(&self) -> bool {
+        matches!(self, Operation::Quantize { .. } | Operation::Dither { .. })
+    }
+
+    /// Check the arguments against an image of `size`, and return the size of the result.  This
+    /// loads any files the operation needs (overlays, fonts, palettes) but doesn't touch any
+    /// pixels.
+    fn output_size(&self, (width, height): (u32, u32)) -> Result<(u32, u32), String> {
+        let at_least = |name: &str, value: f32, min: f32| {
+            if value.is_finite() && value >= min {
+                Ok(())
+            } else {
+                Err(format!("{name} must be at least {min}, not {value}"))
+            }
+        };
+        let between = |name: &str, value: f32, min: f32, max: f32| {
+            if (min..=max).contains(&value) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{name} must be between {min} and {max}, not {value}"
+                ))
+            }
+        };
+        let readable_image = |path: &str| {
+            image::image_dimensions(path)
+                .map(|_| ())
+                .map_err(|e| format!("{path}: {e}"))
+        };
+
+        match self {
+            Operation::Blur { blur_amount, .. } => at_least("BLUR_AMOUNT", *blur_amount, 0.0)?,
+            Operation::Denoise {
+                radius, strength, ..
+            } => {
+                if let Some(radius) = radius.filter(|radius| *radius > 100) {
+                    return Err(format!("--radius must be at most 100, not {radius}"));
+                }
+                between("--strength", *strength, 0.0, 1.0)?;
+            }
+            Operation::MotionBlur { length, .. } => at_least("LENGTH", *length, 0.0)?,
+            Operation::RadialBlur { amount, kind, .. } => match kind {
+                RadialKind::Zoom => between("AMOUNT", *amount, 0.0, 1.0)?,
+                RadialKind::Spin => at_least("AMOUNT", *amount, 0.0)?,
+            },
+            Operation::Crop {
+                x,
+                y,
+                width: crop_width,
+                height: crop_height,
+            } => {
+                // like `DynamicImage::crop`, which keeps whatever part is inside the image
+                let kept_width = (*crop_width).min(width.saturating_sub(*x));
+                let kept_height = (*crop_height).min(height.saturating_sub(*y));
+                if kept_width == 0 || kept_height == 0 {
+                    return Err(format!(
+                        "{crop_width}x{crop_height} at {x},{y} is empty or outside the {width}x{height} image"
+                    ));
+                }
+                return Ok((kept_width, kept_height));
+            }
+            Operation::Rotate {
+                rotate_amount: RotateAmount::Left | RotateAmount::Right,
+            } => return Ok((height, width)),
+            Operation::Generate { .. } | Operation::Fractal => return Ok((800, 800)),
+            Operation::Noise {
+                size,
+                cells,
+                octaves,
+                persistence,
+                ..
+            } => {
+                if *cells == 0 || *cells > size.0 {
+                    return Err(format!(
+                        "--cells must be between 1 and the width, {}, not {cells}",
+                        size.0
+                    ));
+                }
+                if !(1..=16).contains(octaves) {
+                    return Err(format!("--octaves must be between 1 and 16, not {octaves}"));
+                }
+                between("--persistence", *persistence, 0.0, 1.0)?;
+                return Ok(*size);
+            }
+            Operation::Checkerboard { size, squares, .. } => {
+                if *squares == 0 {
+                    return Err("--squares must be at least 1".to_string());
+                }
+                return Ok(*size);
+            }
+            Operation::Stripes {
+                size,
+                stripes,
+                softness,
+                ..
+            } => {
+                if *stripes == 0 {
+                    return Err("--stripes must be at least 1".to_string());
+                }
+                between("--softness", *softness, 0.0, 1.0)?;
+                return Ok(*size);
+            }
+            Operation::Plasma { size, .. } => return Ok(*size),
+            Operation::Seamless { method, overlap } => {
+                between("--overlap", *overlap, 0.0, 1.0)?;
+                if *method == SeamlessMethod::Mirror {
+                    return Ok((width.saturating_mul(2), height.saturating_mul(2)));
+                }
+            }
+            Operation::TilePreview { count } => {
+                return Ok((width.saturating_mul(*count), height.saturating_mul(*count)));
+            }
+            Operation::Overlay {
+                overlay_file,
+                scale,
+                opacity,
+                ..
+            } => {
+                readable_image(overlay_file)?;
+                at_least("--scale", *scale, f32::MIN_POSITIVE)?;
+                between("--opacity", *opacity, 0.0, 1.0)?;
+            }
+            Operation::Quantize { colors, .. } if !(1..=256).contains(colors) => {
+                return Err(format!("COLOURS must be between 1 and 256, not {colors}"));
+            }
+            Operation::Dither { palette, .. } => {
+                dither::load_palette(palette)?;
+            }
+            Operation::ChromaKey {
+                tolerance,
+                softness,
+                ..
+            } => {
+                at_least("--tolerance", *tolerance, 0.0)?;
+                at_least("--softness", *softness, 0.0)?;
+            }
+            Operation::AlphaFromLuma {
+                alpha_file: Some(alpha_file),
+                ..
+            } => {
+                let size = image::image_dimensions(alpha_file)
+                    .map_err(|e| format!("{alpha_file}: {e}"))?;
+                alpha::same_size(size, (width, height))
+                    .map_err(|e| format!("{alpha_file}: {e}"))?;
+            }
+            Operation::Text {
+                font,
+                size,
+                outline_width,
+                ..
+            } => {
+                text::load_font(font.as_deref())?;
+                at_least("--size", *size, 1.0)?;
+                at_least("--outline-width", *outline_width, 0.0)?;
+            }
+            _ => {}
+        }
+        Ok((width, height))
+    }
+
+    /// The colour type of the result of applying the operation to an image of type `colour`.
+    fn output_colour(&self, colour: ColorType) -> ColorType {
+        let depth = Depth::of_colour(colour);
+        let alpha = colour.has_alpha();
+        match self {
+            Operation::Quantize { .. } if alpha => ColorType::Rgba8,
+            Operation::Quantize { .. } | Operation::Dither { .. } => ColorType::Rgb8,
+            Operation::MotionBlur { length, .. } if *length > 1.0 => depth.colour_type(alpha),
+            Operation::RadialBlur { amount, .. } if *amount > 0.0 => depth.colour_type(alpha),
+            Operation::Grayscale => match colour {
+                ColorType::Rgb8 => ColorType::L8,
+                ColorType::Rgba8 => ColorType::La8,
+                ColorType::Rgb16 => ColorType::L16,
+                ColorType::Rgba16 => ColorType::La16,
+                _ => colour,
+            },
+            Operation::Generate { .. } | Operation::Fractal | Operation::Plasma { .. } => {
+                ColorType::Rgb16
+            }
+            Operation::Noise { .. } => ColorType::L16,
+            Operation::Checkerboard { .. } => ColorType::Rgba8,
+            Operation::Stripes { .. } => ColorType::Rgba16,
+            Operation::Seamless { .. }
+            | Operation::TilePreview { .. }
+            | Operation::Overlay { .. }
+            | Operation::Text { .. }
+            | Operation::ColourMatrix { .. } => depth.colour_type(alpha),
+            Operation::Flatten { .. } => depth.colour_type(false),
+            Operation::ChromaKey { .. }
+            | Operation::AlphaFromLuma { .. }
+            | Operation::Premultiply
+            | Operation::Unpremultiply => depth.colour_type(true),
+            Operation::ExtractAlpha => match depth {
+                Depth::Eight => ColorType::L8,
+                Depth::Sixteen => ColorType::L16,
+                Depth::Float => ColorType::Rgb32F,
+            },
+            _ => colour,
+        }
+    }
+}
+
+impl Action for Operation {
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage, String> {
+        apply(img, self.clone())
+    }
+
+    fn check(&self, input: Shape) -> Result<Shape, String> {
+        let (width, height) = self.output_size((input.width, input.height))?;
+        // quantize and dither work on 8-bit sRGB, so linear light is converted first
+        let colour = match (self.reduces_colours(), input.colour) {
+            (true, ColorType::Rgb32F) => ColorType::Rgb8,
+            (true, ColorType::Rgba32F) => ColorType::Rgba8,
+            (_, colour) => colour,
+        };
+        Ok(Shape {
+            width,
+            height,
+            colour: self.output_colour(colour),
+        })
+    }
+
+    /// Whether the operation reads another file, e.g. an overlay image or a font.
+    fn reads_files(&self) -> bool {
+        match self {
+            Operation::Overlay { .. } => true,
+            Operation::AlphaFromLuma { alpha_file, .. } => alpha_file.is_some(),
+            Operation::Text { font, .. } => font.is_some(),
+            Operation::Dither { palette, .. } => dither::builtin_palette(palette).is_none(),
+            _ => false,
+        }
+    }
+
+    fn makes_palette(&self) -> bool {
+        self.reduces_colours()
+    }
+
+    /// Limits on the arguments that cost time for every pixel, or make big text layers.
+    fn check_cost(&self) -> Result<(), String> {
+        let at_most = |name: &str, value: f32, max: f32| {
+            if value <= max {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{name} is limited to {max} when serving or watching, not {value}"
+                ))
+            }
+        };
+        match self {
+            Operation::Blur { blur_amount, .. } => at_most("BLUR_AMOUNT", *blur_amount, 100.0),
+            Operation::Denoise { method, radius, .. } => {
+                let radius = radius.unwrap_or(method.default_radius());
+                at_most("--radius", radius as f32, 10.0)
+            }
+            Operation::Text {
+                size,
+                outline_width,
+                shadow_x,
+                shadow_y,
+                ..
+            } => {
+                at_most("--size", *size, 500.0)?;
+                at_most("--outline-width", *outline_width, 50.0)?;
+                at_most("--shadow-x", shadow_x.unsigned_abs() as f32, 500.0)?;
+                at_most("--shadow-y", shadow_y.unsigned_abs() as f32, 500.0)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// One of the operations in `Operation`, as registered by `Registry::builtin`.
+struct Builtin {
+    command: Command,
+}
+
+impl ImageOp for Builtin {
+    fn name(&self) -> &str {
+        self.command.get_name()
+    }
+
+    fn command(&self) -> Command {
+        self.command.clone()
+    }
+
+    fn parse(&self, matches: &ArgMatches) -> Result<Box<dyn Action>, clap::Error> {
+        Ok(Box::new(Operation::from_arg_matches(matches)?))
+    }
+
+    fn bench_args(&self, input: &str) -> Vec<Vec<String>> {
+        let runs: &[&str] = match self.name() {
+            "blur" => &["4", "4 --method fast", "4 --method box"],
+            "motion-blur" => &["20"],
+            "radial-blur" => &["0.2"],
+            "denoise" => &["median", "bilateral", "nl-means"],
+            "brighten" => &["20"],
+            "crop" => &["10 10 200 200"],
+            "rotate" => &["left"],
+            "generate" => &["30 60 90"],
+            "noise" => &["value", "perlin", "simplex", "worley"],
+            "stripes" => &["--direction diagonal --softness 0.5"],
+            "seamless" => &["", "--method mirror"],
+            "overlay" => return vec![vec![input.to_string(), "--scale".into(), "0.25".into()]],
+            "quantize" => &["16"],
+            "colour-matrix" => &["sepia"],
+            "dither" => &["floyd-steinberg"],
+            "chroma-key" => &["green"],
+            "text" => &["Benchmark --size 48"],
+            _ => &[""],
+        };
+        runs.iter()
+            .map(|run| run.split_whitespace().map(String::from).collect())
+            .collect()
+    }
+}
+
+impl Registry {
+    /// A registry of mirage's own operations.
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::new();
+        let commands = Operation::augment_subcommands(Command::new("operation"));
+        for command in commands.get_subcommands() {
+            registry.register(Builtin {
+                command: command.clone(),
+            });
+        }
+        registry
+    }
+}
+
+#[derive(ValueEnum, Clone)]
+enum RotateAmount {
+    /// rotate 90 degrees
+    Right,
+    /// rotate 180 degrees
+    Flip,
+    /// rotate 270 degrees
+    Left,
+}
+
+/// Run the command line with the operations in `registry`.
+pub fn run(registry: Registry) {
+    let mut command_line = registry.augment(Args::command()).subcommand_required(true);
+    let matches = command_line.clone().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if args.progress {
+        progress::enable();
+    }
+
+    let Some(command) = args.command else {
+        let step = registry
+            .step(&matches)
+            .expect("subcommands are either tools or operations")
+            .unwrap_or_else(|e| e.exit());
+        let (Some(infile), Some(outfile)) = (args.infile, args.outfile) else {
+            command_line
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "operations need both an INFILE and an OUTFILE",
+                )
+                .exit();
+        };
+        let fail = |message: String| -> ! {
+            eprintln!("{message}");
+            std::process::exit(1);
+        };
+
+        if args.dry_run {
+            let mask = (args.region, args.mask.as_deref(), args.feather);
+            dry_run_steps(&infile, &outfile, &[step], mask, args.linear)
+                .unwrap_or_else(|e| fail(e));
+            return;
+        }
+
+        if args.tiled {
+            apply_tiled(&infile, &outfile, &step, args.tile_rows).unwrap_or_else(|e| fail(e));
+            return;
+        }
+
+        // open the image
+        let mut timings = Timings::default();
+        let img = timings
+            .time("decode", || image::open(infile))
+            .expect("Failed to open INFILE.");
+
+        // process the image, or just part of it
+        let mask = mask::build_mask(
+            args.region,
+            args.mask.as_deref(),
+            args.feather,
+            (img.width(), img.height()),
+        )
+        .unwrap_or_else(|e| fail(e));
+        let indexed = step.makes_palette();
+        let img = apply_all(
+            img,
+            vec![step],
+            mask.as_ref(),
+            args.linear,
+            output::stores_float(&outfile),
+            &mut timings,
+        )
+        .unwrap_or_else(|e| fail(e));
+
+        if args.preview {
+            print!(
+                "{}",
+                preview::render_blocks(&img, preview::preview_width(None))
+            );
+        }
+
+        // save the image
+        timings
+            .time("encode", || output::save_image(&img, &outfile, indexed))
+            .unwrap_or_else(|e| fail(format!("{outfile}: {e}")));
+        if args.timings {
+            timings.print();
+        }
+        return;
+    };
+
+    match command {
+        Commands::Bench { infile, runs } => run_bench(&registry, &infile, runs),
+        Commands::Compare {
+            a,
+            b,
+            max_mse,
+            min_psnr,
+            min_ssim,
+            diff,
+            fuzz,
+        } => compare_images(&a, &b, (max_mse, min_psnr, min_ssim), diff, fuzz),
+        Commands::Hash { files, algorithm } => hash_images(&files, algorithm),
+        Commands::Dupes {
+            dir,
+            algorithm,
+            threshold,
+            recursive,
+        } => find_dupes(&dir, algorithm, threshold, recursive),
+        Commands::Montage {
+            patterns,
+            outfile,
+            cols,
+            tile,
+            gap,
+            labels,
+            background,
+        } => {
+            let layout = MontageLayout {
+                columns: cols,
+                tile,
+                gap,
+                background,
+            };
+            make_montage(&patterns, &outfile, &layout, labels);
+        }
+        Commands::Palette {
+            infile,
+            colors,
+            method,
+            json,
+            swatch,
+        } => print_palette(&infile, colors, method, json, swatch),
+        Commands::Shell { infile } => run_shell(&registry, &infile, args.linear, args.preview),
+        Commands::Serve {
+            port,
+            bind,
+            workers,
+            max_upload,
+            max_pixels,
+        } => {
+            let config = serve::ServerConfig {
+                address: format!("{bind}:{port}"),
+                workers,
+                max_upload: max_upload * 1_000_000,
+                max_pixels: max_pixels * 1_000_000,
+            };
+            let linear = args.linear;
+            serve::serve(&config, registry, move |img, steps, format| {
+                apply_all(
+                    img,
+                    steps,
+                    None,
+                    linear,
+                    format == ImageFormat::OpenExr,
+                    &mut Timings::default(),
+                )
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+        }
+        Commands::Session { file, action } => {
+            let flags = (args.linear, args.preview, args.timings, args.dry_run);
+            run_session(&registry, &file, action, flags)
+        }
+        Commands::Watch {
+            dir,
+            out,
+            recipe,
+            interval,
+        } => run_watch(&registry, &dir, &out, &recipe, interval, args.linear),
+        Commands::View {
+            infile,
+            width,
+            ascii,
+        } => {
+            let img = image::open(infile).expect("Failed to open INPUT_FILE.");
+            let width = preview::preview_width(width);
+            if ascii {
+                print!("{}", preview::render_ascii(&img, width));
+            } else {
+                print!("{}", preview::render_blocks(&img, width));
+            }
+        }
+    }
+}
+
+/// Apply `steps` in order, to the whole image or just where `mask` is set, and in linear
+/// light if `linear`.  Linear light results are encoded back to the depth of the input unless
+/// `keep_float`, for output formats that can store them as they are.  Each step is recorded in
+/// `timings`.
+fn apply_all(
+    img: DynamicImage,
+    steps: Vec<Step>,
+    mask: Option<&image::GrayImage>,
+    linear: bool,
+    keep_float: bool,
+    timings: &mut Timings,
+) -> Result<DynamicImage, String> {
+    let depth = Depth::of(&img);
+    let mut img = if linear {
+        timings.time("to linear", || linear::to_linear(img))
+    } else {
+        img
+    };
+    let mut progress = (steps.len() > 1).then(|| Progress::new("operations", steps.len() as u64));
+    for step in steps {
+        let name = step.name();
+        img = timings
+            .time(name, || match mask {
+                Some(mask) => apply_masked(img, &step, mask),
+                None => step.apply(img),
+            })
+            .map_err(|e| format!("{name}: {e}"))?;
+        if let Some(progress) = &mut progress {
+            progress.inc();
+        }
+    }
+    if linear && !keep_float {
+        img = timings.time("from linear", || {
+            linear::to_srgb(img, depth == Depth::Sixteen)
+        });
+    }
+    Ok(img)
+}
+
+/// Check that every step of a watch recipe can be applied to an image of the shape `input`,
+/// in linear light if `linear`, before any of them is run.
+fn check_recipe(steps: &[Step], mut shape: Shape, linear: bool) -> Result<(), String> {
+    if linear {
+        shape.colour = Depth::Float.colour_type(shape.colour.has_alpha());
+    }
+    for step in steps {
+        shape = step
+            .check(shape)
+            .map_err(|e| format!("{}: {e}", step.name()))?;
+    }
+    Ok(())
+}
+
+/// Check that `steps` can be applied to `infile` and the result saved to `outfile`, and
+/// print the size and colour type of the image after each step.  Nothing is written.
+///
+/// `mask` is the `--region`, `--mask` and `--feather` arguments.  Sizes and colour types come
+/// from `Step::check`, so no operation is actually run.
+fn dry_run_steps(
+    infile: &str,
+    outfile: &str,
+    steps: &[Step],
+    mask: (Option<Region>, Option<&str>, f32),
+    linear: bool,
+) -> Result<(), String> {
+    let img = image::open(infile).map_err(|e| format!("{infile}: {e}"))?;
+    println!("{infile}: {}", describe(&img));
+    let depth = Depth::of(&img);
+    let mut shape = Shape::of(&img);
+    drop(img);
+
+    let (region, mask_file, feather) = mask;
+    let mask = mask::build_mask(region, mask_file, feather, (shape.width, shape.height))?;
+
+    if linear {
+        shape.colour = Depth::Float.colour_type(shape.colour.has_alpha());
+    }
+    for step in steps {
+        let name = step.name();
+        let mut result = step.check(shape).map_err(|e| format!("{name}: {e}"))?;
+        if mask.is_some() {
+            if (result.width, result.height) != (shape.width, shape.height) {
+                return Err(format!(
+                    "{name}: --region and --mask only work with operations that keep the image size"
+                ));
+            }
+            // see `mask::blend_masked`
+            let alpha = shape.colour.has_alpha() || result.colour.has_alpha();
+            result.colour = Depth::of_colour(shape.colour).colour_type(alpha);
+        }
+        shape = result;
+        println!(
+            "{name}: {}x{} {:?}",
+            shape.width, shape.height, shape.colour
+        );
+    }
+
+    if linear && !output::stores_float(outfile) && Depth::of_colour(shape.colour) == Depth::Float {
+        let depth = match depth {
+            Depth::Sixteen => Depth::Sixteen,
+            _ => Depth::Eight,
+        };
+        shape.colour = depth.colour_type(shape.colour.has_alpha());
+    }
+    let format = ImageFormat::from_path(outfile).map_err(|e| format!("{outfile}: {e}"))?;
+    let parent = std::path::Path::new(outfile)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    if let Some(parent) = parent {
+        if !parent.is_dir() {
+            return Err(format!("{outfile}: {} is not a folder", parent.display()));
+        }
+    }
+    // encoding a single pixel of the right colour type shows what the file would hold
+    let pixel = DynamicImage::new(1, 1, shape.colour);
+    let indexed = steps.iter().any(Step::makes_palette);
+    let encoded =
+        output::encode_image(&pixel, format, indexed).map_err(|e| format!("{outfile}: {e}"))?;
+    let saved = image::load_from_memory(&encoded).map_err(|e| format!("{outfile}: {e}"))?;
+    println!(
+        "{outfile}: {}x{} {:?} as {format:?}",
+        shape.width,
+        shape.height,
+        saved.color()
+    );
+    Ok(())
+}
+
+/// Apply a single operation to the image.  The arguments should already have been checked
+/// with `Action::check`; the only errors left are files that can't be read.
+fn apply(mut img: DynamicImage, operation: Operation) -> Result<DynamicImage, String> {
+    if linear::is_linear(&img) && operation.reduces_colours() {
+        img = linear::to_srgb(img, false);
+    }
+
+    match operation {
+        Operation::Blur {
+            blur_amount,
+            method,
+        } => img = blur(img, blur_amount, method),
+        Operation::MotionBlur { length, angle } => img = blur::motion_blur(img, length, angle),
+        Operation::RadialBlur {
+            amount,
+            kind,
+            centre_x,
+            centre_y,
+        } => img = blur::radial_blur(img, kind, amount, (centre_x, centre_y)),
+        Operation::Denoise {
+            method,
+            radius,
+            strength,
+        } => {
+            let radius = radius.unwrap_or(method.default_radius());
+            img = denoise::denoise(img, method, radius, strength);
+        }
+        Operation::Brighten { brighten_amount } => img = brighten(img, brighten_amount),
+        Operation::Crop {
+            x,
+            y,
+            width,
+            height,
+        } => img = crop(&mut img, x, y, width, height),
+        Operation::Rotate { rotate_amount } => img = rotate(img, rotate_amount),
+        Operation::Invert => invert(&mut img),
+        Operation::Grayscale => img = grayscale(img),
+        Operation::Generate {
+            red_amount,
+            green_amount,
+            blue_amount,
+        } => img = generate(red_amount, green_amount, blue_amount),
+        Operation::Fractal => img = fractal(),
+        Operation::Noise {
+            kind,
+            size,
+            cells,
+            octaves,
+            persistence,
+            seed,
+        } => {
+            let settings = NoiseSettings {
+                cells,
+                octaves,
+                persistence,
+                seed,
+            };
+            img = texture::noise(kind, size, &settings);
+        }
+        Operation::Checkerboard {
+            size,
+            squares,
+            colour,
+            background,
+        } => img = texture::checkerboard(size, squares, colour, background),
+        Operation::Stripes {
+            size,
+            stripes,
+            direction,
+            softness,
+            colour,
+            background,
+        } => img = texture::stripes(size, stripes, direction, softness, colour, background),
+        Operation::Plasma { size, seed } => img = texture::plasma(size, seed),
+        Operation::Seamless { method, overlap } => img = seamless::seamless(img, method, overlap),
+        Operation::TilePreview { count } => img = seamless::tile_preview(img, count),
+        Operation::ColourMatrix { filters } => {
+            img = colour_matrix::colour_matrix(img, &filters);
+        }
+        Operation::Flatten { background } => img = alpha::flatten(img, background),
+        Operation::ChromaKey {
+            key,
+            tolerance,
+            softness,
+        } => img = alpha::chroma_key(img, key, tolerance, softness),
+        Operation::ExtractAlpha => img = alpha::extract_alpha(&img),
+        Operation::AlphaFromLuma { alpha_file, invert } => {
+            let source = alpha_file
+                .map(|file| image::open(&file).map_err(|e| format!("{file}: {e}")))
+                .transpose()?;
+            img = alpha::alpha_from_luminance(img, source.as_ref(), invert)?;
+        }
+        Operation::Premultiply => img = alpha::premultiply(img),
+        Operation::Unpremultiply => img = alpha::unpremultiply(img),
+        Operation::Dither { method, palette } => {
+            let palette = dither::load_palette(&palette)?;
+            img = dither::dither(img, &palette, method);
+        }
+        Operation::Quantize { colors, method } => {
+            img = palette::quantize(img, colors.clamp(1, 256), method)
+        }
+        Operation::Overlay {
+            overlay_file,
+            gravity,
+            x_offset,
+            y_offset,
+            scale,
+            opacity,
+            blend,
+        } => {
+            let top = image::open(&overlay_file).map_err(|e| format!("{overlay_file}: {e}"))?;
+            img = overlay::overlay(
+                img,
+                &top,
+                gravity,
+                (x_offset, y_offset),
+                scale,
+                opacity,
+                blend,
+            );
+        }
+        Operation::Text {
+            text,
+            font,
+            size,
+            colour,
+            gravity,
+            x_offset,
+            y_offset,
+            outline_width,
+            outline_colour,
+            shadow_x,
+            shadow_y,
+            shadow_colour,
+        } => {
+            let font = text::load_font(font.as_deref())?;
+            let style = TextStyle {
+                size,
+                colour,
+                outline_width,
+                outline_colour,
+                shadow_offset: (shadow_x, shadow_y),
+                shadow_colour,
+            };
+            img = text::text(img, &text, &font, &style, gravity, (x_offset, y_offset));
+        }
+    }
+
+    Ok(img)
+}
+
+/// Apply an operation, then keep its result only where the mask is set.
+fn apply_masked(
+    img: DynamicImage,
+    step: &Step,
+    mask: &image::GrayImage,
+) -> Result<DynamicImage, String> {
+    let processed = step.apply(img.clone())?;
+    if processed.width() != img.width() || processed.height() != img.height() {
+        return Err(
+            "--region and --mask only work with operations that keep the image size".to_string(),
+        );
+    }
+    Ok(mask::blend_masked(&img, &processed, mask))
+}
+
+/// Apply an operation to a huge PNG a strip at a time (see `tiled`).
+fn apply_tiled(infile: &str, outfile: &str, step: &Step, tile_rows: u32) -> Result<(), String> {
+    let shape = tiled::read_shape(infile)?;
+    step.check(shape)
+        .map_err(|e| format!("{}: {e}", step.name()))?;
+
+    let mut plan = tiled::TilePlan {
+        rows_per_tile: tile_rows,
+        overlap: 0,
+        rows: None,
+    };
+    let operation = (step.action() as &dyn Any).downcast_ref::<Operation>();
+    match operation.cloned() {
+        Some(
+            operation @ (Operation::Invert | Operation::Brighten { .. } | Operation::Grayscale),
+        ) => tiled::process_tiled(infile, outfile, &plan, |strip| {
+            apply(strip, operation.clone())
+        }),
+        Some(Operation::Blur {
+            blur_amount,
+            method,
+        }) => {
+            plan.overlap = method.reach(blur_amount);
+            tiled::process_tiled(infile, outfile, &plan, |strip| {
+                Ok(blur(strip, blur_amount, method))
+            })
+        }
+        Some(Operation::Denoise {
+            method,
+            radius,
+            strength,
+        }) => {
+            let radius = radius.unwrap_or(method.default_radius());
+            plan.overlap = method.reach(radius);
+            tiled::process_tiled(infile, outfile, &plan, |strip| {
+                Ok(denoise::denoise(strip, method, radius, strength))
+            })
+        }
+        Some(Operation::Crop {
+            x,
+            y,
+            width,
+            height,
+        }) => {
+            plan.rows = Some(y..y.saturating_add(height));
+            tiled::process_tiled(infile, outfile, &plan, |strip| {
+                let x = x.min(strip.width());
+                let width = width.min(strip.width() - x);
+                Ok(strip.crop_imm(x, 0, width, strip.height()))
+            })
+        }
+        _ => Args::command()
+            .subcommand_required(true)
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--tiled only works with invert, brighten, grayscale, blur, denoise and crop",
+            )
+            .exit(),
+    }
+}
+
+/// Most images kept for `undo` in the shell.
+const UNDO_LEVELS: usize = 20;
+
+/// Run an interactive **shell** on the image: read operations from stdin a line at a time and
+/// apply them to the image kept in memory.
+fn run_shell(registry: &Registry, infile: &str, linear: bool, preview: bool) {
+    let img = image::open(infile).expect("Failed to open INPUT_FILE.");
+    let depth = Depth::of(&img);
+    let mut img = if linear { linear::to_linear(img) } else { img };
+    let mut indexed = false;
+    let mut history = History::new(UNDO_LEVELS);
+
+    println!(
+        "{infile}: {}. Type help for a list of commands.",
+        describe(&img)
+    );
+    let commands = registry
+        .augment(ShellLine::command())
+        .subcommand_required(true);
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("mirage> ");
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        line.clear();
+        match stdin.read_line(&mut line) {
+            Ok(0) => {
+                println!();
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{e}");
+                break;
+            }
+        }
+
+        let words = match shell::split_words(&line) {
+            Ok(words) if words.is_empty() => continue,
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+        let parsed = commands
+            .clone()
+            .try_get_matches_from(&words)
+            .and_then(|matches| Ok((ShellLine::from_arg_matches(&matches)?, matches)));
+        let (line, matches) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let _ = e.print();
+                continue;
+            }
+        };
+
+        let Some(command) = line.command else {
+            let step = match registry
+                .step(&matches)
+                .expect("subcommands are either shell commands or operations")
+            {
+                Ok(step) => step,
+                Err(e) => {
+                    let _ = e.print();
+                    continue;
+                }
+            };
+            if let Err(e) = apply_in_shell(&step, (&mut img, &mut indexed), &mut history) {
+                eprintln!("{}: {e}", step.name());
+                continue;
+            }
+            println!("{}", describe(&img));
+            if preview {
+                print!(
+                    "{}",
+                    preview::render_blocks(&img, preview::preview_width(None))
+                );
+            }
+            continue;
+        };
+
+        match command {
+            ShellCommand::Undo => match history.pop() {
+                Some(previous) => {
+                    (img, indexed) = previous;
+                    println!("{}", describe(&img));
+                }
+                None => eprintln!("Nothing to undo"),
+            },
+            ShellCommand::Save { outfile } => {
+                let mut out = img.clone();
+                if linear && !output::stores_float(&outfile) {
+                    out = linear::to_srgb(out, depth == Depth::Sixteen);
+                }
+                match output::save_image(&out, &outfile, indexed) {
+                    Ok(()) => println!("Saved {outfile}"),
+                    Err(e) => eprintln!("Failed writing {outfile}: {e}"),
+                }
+            }
+            ShellCommand::Info => println!(
+                "{infile}: {}, {} operation(s) can be undone",
+                describe(&img),
+                history.len()
+            ),
+            ShellCommand::View { width, ascii } => {
+                let width = preview::preview_width(width);
+                if ascii {
+                    print!("{}", preview::render_ascii(&img, width));
+                } else {
+                    print!("{}", preview::render_blocks(&img, width));
+                }
+            }
+            ShellCommand::Quit => break,
+        }
+    }
+}
+
+/// Apply `step` to the shell's image, keeping the old image for `undo`.  `indexed` is whether
+/// the image has been reduced to a palette, which undo also puts back.  If the step fails, the
+/// image and its history are left as they were.
+fn apply_in_shell(
+    step: &Step,
+    (img, indexed): (&mut DynamicImage, &mut bool),
+    history: &mut History<(DynamicImage, bool)>,
+) -> Result<(), String> {
+    let result = step.apply(img.clone())?;
+    history.push((std::mem::replace(img, result), *indexed));
+    *indexed |= step.makes_palette();
+    Ok(())
+}
+
+/// The size and colour type of an image, e.g. `640x480 Rgb8`.
+fn describe(img: &DynamicImage) -> String {
+    format!("{}x{} {:?}", img.width(), img.height(), img.color())
+}
+
+/// **Watch** `dir` and apply the operations in `recipe_file` to every image that is added or
+/// changed, writing the results to `out`.  This never returns.
+fn run_watch(
+    registry: &Registry,
+    dir: &str,
+    out: &str,
+    recipe_file: &str,
+    interval: Duration,
+    linear: bool,
+) {
+    let fail = |message: String| -> ! {
+        eprintln!("{message}");
+        std::process::exit(1);
+    };
+
+    let recipe = Recipe::load(recipe_file).unwrap_or_else(|e| fail(e));
+    if let Some(format) = &recipe.format {
+        if !ImageFormat::from_extension(format).is_some_and(|f| f.can_write()) {
+            fail(format!("{recipe_file}: can't write {format} images"));
+        }
+    }
+    let mut steps = Vec::new();
+    for spec in recipe.operations {
+        let words = spec
+            .into_words()
+            .unwrap_or_else(|e| fail(format!("{recipe_file}: {e}")));
+        let step = registry
+            .parse(&words)
+            .unwrap_or_else(|e| fail(format!("{recipe_file}: {}: {e}", words.join(" "))));
+        step.check_cost()
+            .unwrap_or_else(|e| fail(format!("{recipe_file}: {}: {e}", words.join(" "))));
+        steps.push(step);
+    }
+
+    let indexed = steps.iter().any(Step::makes_palette);
+
+    std::fs::create_dir_all(out).unwrap_or_else(|e| fail(format!("{out}: {e}")));
+    let in_dir = Path::new(dir)
+        .canonicalize()
+        .unwrap_or_else(|e| fail(format!("{dir}: {e}")));
+    if Path::new(out).canonicalize().ok().as_ref() == Some(&in_dir) {
+        fail(format!(
+            "{out}: results can't go in the folder being watched"
+        ));
+    }
+
+    println!("Watching {dir} for images");
+    let mut watcher = Watcher::new(&in_dir);
+    let mut outputs = Outputs::default();
+    loop {
+        for input in watcher.poll().unwrap_or_else(|e| fail(e)) {
+            let name = input.file_name().unwrap_or_default().to_string_lossy();
+            let mut output = Path::new(out).join(&*name);
+            if let Some(format) = &recipe.format {
+                output.set_extension(format);
+            }
+            if let Err(other) = outputs.claim(&output, &input) {
+                eprintln!(
+                    "{name}: skipped, as {} already goes to {}",
+                    other.display(),
+                    output.display()
+                );
+                continue;
+            }
+            if watch::up_to_date(&input, &output) {
+                continue;
+            }
+
+            let started = Instant::now();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+                let img = image::open(&input).map_err(|e| e.to_string())?;
+                check_recipe(&steps, Shape::of(&img), linear)?;
+                let outfile = output.to_string_lossy();
+                let img = apply_all(
+                    img,
+                    steps.clone(),
+                    None,
+                    linear,
+                    output::stores_float(&outfile),
+                    &mut Timings::default(),
+                )?;
+                output::save_image(&img, &outfile, indexed)
+            }));
+            match result {
+                Ok(Ok(())) => println!(
+                    "{name}: wrote {} in {:.2?}",
+                    output.display(),
+                    started.elapsed()
+                ),
+                Ok(Err(e)) => eprintln!("{name}: failed: {e}"),
+                Err(_) => eprintln!("{name}: failed"),
+            }
+        }
+        thread::sleep(interval.max(Duration::from_millis(100)));
+    }
+}
+
+/// Carry out a **session** action on the session in `file`.  `flags` are the global `--linear`,
+/// `--preview`, `--timings` and `--dry-run` flags, which apply to `render`.
+fn run_session(
+    registry: &Registry,
+    file: &str,
+    action: SessionAction,
+    flags: (bool, bool, bool, bool),
+) {
+    let (linear, preview, timings, dry_run) = flags;
+    let fail = |message: String| -> ! {
+        eprintln!("{message}");
+        std::process::exit(1);
+    };
+
+    if let SessionAction::New { infile, force } = action {
+        if !force && std::path::Path::new(file).exists() {
+            fail(format!("{file} already exists; use --force to replace it"));
+        }
+        let session = Session::new(&infile).unwrap_or_else(|e| fail(e));
+        session.save(file).unwrap_or_else(|e| fail(e));
+        println!("Started {file} for {}", session.source.display());
+        return;
+    }
+
+    let mut session = Session::load(file).unwrap_or_else(|e| fail(e));
+    match action {
+        SessionAction::New { .. } => unreachable!(),
+        SessionAction::Add { words } => {
+            if let Err(e) = registry.parse(&words) {
+                e.exit();
+            }
+            println!("{}. {}", session.operations.len() + 1, words.join(" "));
+            session.add(words);
+        }
+        SessionAction::List => {
+            println!("{}", session.source.display());
+            for (i, words) in session.operations.iter().enumerate() {
+                println!("{}. {}", i + 1, words.join(" "));
+            }
+            return;
+        }
+        SessionAction::Undo => match session.undo() {
+            Some(words) => println!("Undid {}", words.join(" ")),
+            None => fail("Nothing to undo".to_string()),
+        },
+        SessionAction::Redo => match session.redo() {
+            Some(words) => println!("Redid {}", words.join(" ")),
+            None => fail("Nothing to redo".to_string()),
+        },
+        SessionAction::Remove { position } => match session.remove(position) {
+            Some(words) => println!("Removed {}", words.join(" ")),
+            None => fail(format!("There is no operation {position}")),
+        },
+        SessionAction::Render { outfile } => {
+            let source = &session.source;
+            let same_file = std::path::Path::new(&outfile)
+                .canonicalize()
+                .is_ok_and(|path| path == *source);
+            if same_file {
+                fail(format!("Refusing to overwrite the original {outfile}"));
+            }
+
+            let steps = session
+                .operations
+                .iter()
+                .map(|words| registry.parse(words))
+                .collect::<Result<Vec<Step>, _>>()
+                .unwrap_or_else(|e| e.exit());
+            if dry_run {
+                let source = source.to_string_lossy();
+                let no_mask = (None, None, 0.0);
+                dry_run_steps(&source, &outfile, &steps, no_mask, linear)
+                    .unwrap_or_else(|e| fail(e));
+                return;
+            }
+            let indexed = steps.iter().any(Step::makes_palette);
+            let mut times = Timings::default();
+            let img = times
+                .time("decode", || image::open(source))
+                .expect("Failed to open the session's original image.");
+            let img = apply_all(
+                img,
+                steps,
+                None,
+                linear,
+                output::stores_float(&outfile),
+                &mut times,
+            )
+            .unwrap_or_else(|e| fail(e));
+            if preview {
+                print!(
+                    "{}",
+                    preview::render_blocks(&img, preview::preview_width(None))
+                );
+            }
+            times
+                .time("encode", || output::save_image(&img, &outfile, indexed))
+                .unwrap_or_else(|e| fail(format!("{outfile}: {e}")));
+            if timings {
+                times.print();
+            }
+            return;
+        }
+    }
+    session.save(file).unwrap_or_else(|e| fail(e));
+}
+
+/// **Bench**: run every operation on `infile` `runs` times and print a table of how long they
+/// took.
+fn run_bench(registry: &Registry, infile: &str, runs: usize) {
+    let img = image::open(infile).expect("Failed to open INPUT_FILE.");
+    let megapixels = img.width() as f64 * img.height() as f64 / 1e6;
+    let runs = runs.max(1);
+
+    println!(
+        "{infile}: {}, {runs} runs of each operation",
+        describe(&img)
+    );
+    println!(
+        "{:<28}  {:>10}  {:>10}  {:>10}  {:>8}",
+        "operation", "min", "median", "max", "Mpx/s"
+    );
+    let runs_of_each = registry.iter().flat_map(|op| {
+        op.bench_args(infile)
+            .into_iter()
+            .map(|args| [vec![op.name().to_string()], args].concat())
+    });
+    for words in runs_of_each {
+        let line = words
+            .iter()
+            .map(|word| if word == infile { "INFILE" } else { word })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let step = match registry.parse(&words) {
+            Ok(step) => step,
+            Err(e) => {
+                let e = e.to_string();
+                let reason = e.lines().next().unwrap_or_default();
+                println!(
+                    "{line:<28}  skipped, {}",
+                    reason.trim_start_matches("error: ")
+                );
+                continue;
+            }
+        };
+
+        let mut times: Vec<Duration> = (0..runs)
+            .map(|_| {
+                let copy = img.clone();
+                let start = Instant::now();
+                let result = step.apply(copy);
+                let elapsed = start.elapsed();
+                drop(result);
+                elapsed
+            })
+            .collect();
+        times.sort();
+
+        let median = times[runs / 2];
+        println!(
+            "{line:<28}  {:>10.2?}  {:>10.2?}  {:>10.2?}  {:>8.1}",
+            times[0],
+            median,
+            times[runs - 1],
+            megapixels / median.as_secs_f64()
+        );
+    }
+}
+
+/// **Compare** two images, print the metrics and exit non-zero if any threshold is not met.
+fn compare_images(
+    a: &str,
+    b: &str,
+    thresholds: (Option<f64>, Option<f64>, Option<f64>),
+    diff: Option<String>,
+    fuzz: u8,
+) {
+    // exit status 1 is kept for images that differ
+    let fail = |message: String| -> ! {
+        eprintln!("{message}");
+        std::process::exit(2);
+    };
+    let img_a = image::open(a).unwrap_or_else(|e| fail(format!("{a}: {e}")));
+    let img_b = image::open(b).unwrap_or_else(|e| fail(format!("{b}: {e}")));
+
+    let metrics = compare::compare(&img_a, &img_b)
+        .unwrap_or_else(|e| fail(format!("Can't compare {a} and {b}: {e}")));
+
+    if img_a.color() != img_b.color() {
+        println!(
+            "Colour types differ: {:?} vs {:?}",
+            img_a.color(),
+            img_b.color()
+        );
+    }
+    println!("MSE:  {:.4}", metrics.mse);
+    println!("PSNR: {:.2} dB", metrics.psnr);
+    println!("SSIM: {:.5}", metrics.ssim);
+
+    if let Some(diff) = diff {
+        let diff_image = compare::diff_image(&img_a, &img_b, fuzz);
+        output::save_image(&diff_image, &diff, false)
+            .unwrap_or_else(|e| fail(format!("{diff}: {e}")));
+    }
+
+    let (max_mse, min_psnr, min_ssim) = thresholds;
+    let mut failures = Vec::new();
+    if max_mse.is_some_and(|max| metrics.mse > max) {
+        failures.push("MSE is above --max-mse");
+    }
+    if min_psnr.is_some_and(|min| metrics.psnr < min) {
+        failures.push("PSNR is below --min-psnr");
+    }
+    if min_ssim.is_some_and(|min| metrics.ssim < min) {
+        failures.push("SSIM is below --min-ssim");
+    }
+    if !failures.is_empty() {
+        eprintln!("Images differ: {}", failures.join(", "));
+        std::process::exit(1);
+    }
+}
+
+/// Print the perceptual **hash**es of each file, one file per line.
+fn hash_images(files: &[String], algorithm: Option<HashAlgorithm>) {
+    let algorithms = match algorithm {
+        Some(algorithm) => vec![algorithm],
+        None => HashAlgorithm::value_variants().to_vec(),
+    };
+
+    let mut progress = Progress::new("hash", files.len() as u64);
+    for file in files {
+        let img = image::open(file).expect("Failed to open FILES.");
+        let hashes: Vec<String> = algorithms
+            .iter()
+            .map(|&algorithm| {
+                let name = algorithm.to_possible_value().unwrap();
+                let hash = hash::image_hash(&img, algorithm);
+                format!("{}:{hash:016x}", name.get_name())
+            })
+            .collect();
+        println!("{}  {file}", hashes.join(" "));
+        progress.inc();
+    }
+}
+
+/// Find near-**dupes** in a directory and print them in groups.
+fn find_dupes(dir: &str, algorithm: HashAlgorithm, threshold: u32, recursive: bool) {
+    let files =
+        hash::image_files(std::path::Path::new(dir), recursive).expect("Failed to read DIR.");
+
+    let mut hashes = Vec::new();
+    let mut progress = Progress::new("dupes", files.len() as u64);
+    for file in files {
+        match image::open(&file) {
+            Ok(img) => hashes.push((file, hash::image_hash(&img, algorithm))),
+            Err(e) => eprintln!("Skipping {}: {e}", file.display()),
+        }
+        progress.inc();
+    }
+    drop(progress);
+
+    let groups = hash::group_similar(&hashes, threshold);
+    if groups.is_empty() {
+        println!("No similar images found.");
+    }
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        for file in group {
+            println!("{}", file.display());
+        }
+    }
+}
+
+/// Build a **montage** of every image matching `patterns` and save it to `outfile`.
+fn make_montage(patterns: &[String], outfile: &str, layout: &MontageLayout, labels: bool) {
+    let files = montage::expand_patterns(patterns).expect("Failed to expand FILES.");
+
+    let mut images = Vec::new();
+    let mut progress = Progress::new("montage", files.len() as u64);
+    for file in files {
+        match image::open(&file) {
+            Ok(img) => images.push((file, img)),
+            Err(e) => eprintln!("Skipping {}: {e}", file.display()),
+        }
+        progress.inc();
+    }
+    drop(progress);
+    if images.is_empty() {
+        eprintln!("No images matched {}", patterns.join(" "));
+        std::process::exit(1);
+    }
+
+    let font = labels.then(|| text::load_font(None).expect("Failed to load the bundled font."));
+    let sheet = montage::montage(&images, layout, font.as_ref());
+    output::save_image(&sheet, outfile, false).unwrap_or_else(|e| {
+        eprintln!("{outfile}: {e}");
+        std::process::exit(1);
+    });
+}
+
+/// Print the dominant colours of an image as text or JSON, and optionally draw them.
+fn print_palette(
+    infile: &str,
+    colours: usize,
+    method: PaletteMethod,
+    json: bool,
+    swatch: Option<String>,
+) {
+    let img = image::open(infile).expect("Failed to open INPUT_FILE.");
+    let swatches = palette::extract_palette(&img, colours, method);
+
+    if json {
+        let entries: Vec<String> = swatches
+            .iter()
+            .map(|swatch| {
+                let [r, g, b] = swatch.colour.0;
+                format!(
+                    "  {{\"hex\": \"{}\", \"rgb\": [{r}, {g}, {b}], \"fraction\": {:.4}}}",
+                    palette::hex(swatch.colour),
+                    swatch.fraction
+                )
+            })
+            .collect();
+        println!("[\n{}\n]", entries.join(",\n"));
+    } else {
+        for swatch in &swatches {
+            println!(
+                "{}  {:5.1}%",
+                palette::hex(swatch.colour),
+                swatch.fraction * 100.0
+            );
+        }
+    }
+
+    if let Some(swatch) = swatch {
+        output::save_image(&palette::swatch_strip(&swatches, 64), &swatch, true)
+            .expect("Failed writing SWATCH_FILE.");
+    }
+}
+
+/// **Blur** the image by the given amount.
+///
+/// For the Gaussian methods the amount is the standard deviation (sigma); for a box blur it's
+/// the radius in pixels.
+fn blur(img: DynamicImage, blur_amount: f32, method: BlurMethod) -> DynamicImage {
+    match method {
+        BlurMethod::Gaussian => img.blur(blur_amount),
+        BlurMethod::Fast => blur::fast_gaussian(img, blur_amount),
+        BlurMethod::Box => blur::box_blur(img, blur_amount.round().max(0.0) as u32),
+    }
+}
+
+/// **Brighten** the image by the given amount, in 8-bit steps whatever the depth of the image.
+fn brighten(img: DynamicImage, brighten_amount: i32) -> DynamicImage {
+    match Depth::of(&img) {
+        Depth::Eight => return img.brighten(brighten_amount),
+        Depth::Sixteen => return img.brighten(brighten_amount.saturating_mul(257)),
+        Depth::Float => {}
+    }
+
+    // the image crate only brightens integer channels
+    let had_alpha = img.color().has_alpha();
+    let step = brighten_amount as f32 / 255.0;
+    let mut rgba = img.into_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = (pixel[c] + step).clamp(0.0, 1.0);
+        }
+    }
+    Depth::Float.restore(rgba, had_alpha)
+}
+
+/// **Crop** the image to a fixed width/height starting at the given x/y position.
+fn crop(img: &mut DynamicImage, x: u32, y: u32, width: u32, height: u32) -> DynamicImage {
+    img.crop(x, y, width, height)
+}
+
+/// **Rotate** the image 90 degrees left/right or flip it by rotating 180 degrees.
+fn rotate(img: DynamicImage, rotate_amount: RotateAmount) -> DynamicImage {
+    match rotate_amount {
+        RotateAmount::Right => img.rotate90(),
+        RotateAmount::Flip => img.rotate180(),
+        RotateAmount::Left => img.rotate270(),
+    }
+}
+
+/// **Invert** the image colours (create a negative).
+fn invert(img: &mut DynamicImage) {
+    img.invert();
+}
+
+/// Convert image to **Grayscale** by removing all colour.
+fn grayscale(img: DynamicImage) -> DynamicImage {
+    img.grayscale()
+}
+
+/// **Generate** a fun image.
+fn generate(red: u8, green: u8, blue: u8) -> DynamicImage {
+    let width = 800;
+    let height = 800;
+
+    let mut imgbuf = image::ImageBuffer::new(width, height);
+
+    // Iterate over the coordinates and pixels of the image
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        // Generate a pretty fractal and scale by supplied colour values
+        // let r =
+        //     (255 - (0.333 * (x - 20) as f32) as u8) * (255 - (0.333 * (y - 20) as f32) as u8) * red;
+        // let g = ((0.333 * (x - 20) as f32) as u8) * (255 - (0.333 * (y - 20) as f32) as u8) * green;
+        // let b = (255 - (0.333 * (x - 20) as f32) as u8) * ((0.333 * (y - 20) as f32) as u8) * blue;
+
+        // Generate a colour gradient scaled by supplied colour values
+        let y_percent = y as f32 / height as f32;
+        let x_percent = x as f32 / width as f32;
+
+        let r = (1.0 - (y_percent - x_percent).abs()) * (red as f32 / 255.0);
+        let g = y_percent * (green as f32 / 255.0);
+        let b = x_percent * (blue as f32 / 255.0);
+
+        // 16 bits per channel, so the gradient doesn't show bands
+        *pixel = image::Rgb([r, g, b].map(|c| (c * 65535.0).round() as u16));
+
+        // set pixel values for each pixel - simple color swatch
+        // *pixel = image::Rgb([red, green, blue]);
+    }
+
+    image::DynamicImage::ImageRgb16(imgbuf)
+}
+
+// This code was adapted from https://github.com/PistonDevelopers/image
+/// Generate a **fractal** image.
+fn fractal() -> DynamicImage {
+    let width = 800;
+    let height = 800;
+
+    let mut imgbuf = image::ImageBuffer::new(width, height);
+
+    let scale_x = 3.0 / width as f32;
+    let scale_y = 3.0 / height as f32;
+    let mut progress = Progress::new("fractal", height as u64);
+
+    // Iterate over the coordinates and pixels of the image
+    for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
+        if x == 0 {
+            progress.set(y as u64);
+        }
+
+        // Use red and blue to be a pretty gradient background
+        let red = 0.3 * x as f32;
+        let blue = 0.3 * y as f32;
+
+        // Use green as the fractal foreground (here is the fractal math part)
+        let cx = y as f32 * scale_x - 1.5;
+        let cy = x as f32 * scale_y - 1.5;
+
+        let c = num_complex::Complex::new(-0.4, 0.6);
+        let mut z = num_complex::Complex::new(cx, cy);
+
+        let mut green = 0;
+        while green < 255 && z.norm() <= 2.0 {
+            z = z * z + c;
+            green += 1;
+        }
+
+        // Actually set the pixel. red, green, and blue are 0-255, stored in 16 bits
+        *pixel = image::Rgb([red, green as f32, blue].map(|c| (c * 257.0).round() as u16));
+    }
+
+    image::DynamicImage::ImageRgb16(imgbuf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_agrees_with_apply() {
+        let registry = Registry::builtin();
+        let lines = [
+            "blur 1",
+            "blur 1 --method fast",
+            "blur 1 --method box",
+            "motion-blur 1",
+            "motion-blur 3",
+            "radial-blur 0",
+            "radial-blur 0.2",
+            "denoise median",
+            "brighten 10",
+            "crop 1 2 4 3",
+            "rotate left",
+            "invert",
+            "grayscale",
+            "noise perlin --size 16x8 --cells 4",
+            "checkerboard --size 16x8",
+            "stripes --size 16x8",
+            "plasma --size 16x8",
+            "seamless",
+            "seamless --method mirror",
+            "tile-preview",
+            "quantize 4",
+            "colour-matrix sepia",
+            "dither floyd-steinberg",
+            "flatten",
+            "chroma-key green",
+            "extract-alpha",
+            "alpha-from-luma",
+            "premultiply",
+            "unpremultiply",
+            "text A --size 8",
+        ];
+        let colours = [
+            ColorType::L8,
+            ColorType::La8,
+            ColorType::Rgb8,
+            ColorType::Rgba8,
+            ColorType::L16,
+            ColorType::La16,
+            ColorType::Rgb16,
+            ColorType::Rgba16,
+            ColorType::Rgb32F,
+            ColorType::Rgba32F,
+        ];
+        for line in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let step = registry.parse(&words).unwrap();
+            for colour in colours {
+                let img = DynamicImage::new(8, 6, colour);
+                let checked = step.check(Shape::of(&img)).unwrap();
+                let applied = Shape::of(&step.apply(img).unwrap());
+                assert_eq!(checked, applied, "{line} on {colour:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn check_rejects_bad_arguments() {
+        let registry = Registry::builtin();
+        let shape = Shape {
+            width: 10,
+            height: 10,
+            colour: ColorType::Rgb8,
+        };
+        for line in ["crop 10 0 5 5", "blur -- -1", "seamless --overlap 2"] {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let step = registry.parse(&words).unwrap();
+            assert!(step.check(shape).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn apply_checks_first() {
+        let registry = Registry::builtin();
+        let img = DynamicImage::new_rgb8(20, 10);
+        for line in [
+            "crop 5000 5000 10 10",
+            "denoise median --radius 100000",
+            "blur -- -2",
+            "quantize 0",
+            "radial-blur 5",
+            "seamless --overlap 3",
+            "noise perlin --cells 0",
+            "noise perlin --octaves 100",
+            "stripes --stripes 0",
+            "checkerboard --squares 0",
+            "overlay missing.png",
+            "text Hi --font missing.ttf",
+        ] {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let step = registry.parse(&words).unwrap();
+            assert!(step.apply(img.clone()).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn failed_shell_steps_change_nothing() {
+        let registry = Registry::builtin();
+        let original = DynamicImage::new_rgb8(20, 10);
+        let mut img = original.clone();
+        let mut indexed = false;
+        let mut history = History::new(UNDO_LEVELS);
+
+        for line in ["crop 5000 5000 10 10", "overlay missing.png"] {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let step = registry.parse(&words).unwrap();
+            assert!(
+                apply_in_shell(&step, (&mut img, &mut indexed), &mut history).is_err(),
+                "{line}"
+            );
+            assert_eq!(img, original);
+            assert_eq!(history.len(), 0);
+        }
+
+        let step = registry.parse(&["crop", "0", "0", "5", "5"]).unwrap();
+        apply_in_shell(&step, (&mut img, &mut indexed), &mut history).unwrap();
+        assert_eq!((img.width(), img.height()), (5, 5));
+        assert_eq!(history.pop(), Some((original, false)));
+    }
+
+    #[test]
+    fn tiled_and_untiled_results_match() {
+        let registry = Registry::builtin();
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(40, 37, |x, y| {
+            let speckle = (x * 7919 + y * 104729) % 61;
+            image::Rgb([(x * 6) as u8, (y * 6) as u8, (speckle * 4) as u8])
+        }));
+        let dir = std::env::temp_dir().join(format!("mirage-tiled-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let infile = dir.join("in.png");
+        let outfile = dir.join("out.png");
+        let (infile, outfile) = (infile.to_str().unwrap(), outfile.to_str().unwrap());
+        img.save(infile).unwrap();
+
+        for line in [
+            "blur 3",
+            "blur 3 --method fast",
+            "blur 3 --method box",
+            "denoise median",
+            "brighten 20",
+            "crop 5 7 20 20",
+        ] {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let step = registry.parse(&words).unwrap();
+            // strips of 2 rows, fewer than any of the blurs reach
+            apply_tiled(infile, outfile, &step, 2).unwrap();
+            let tiled = image::open(outfile).unwrap();
+            assert_eq!(tiled, step.apply(img.clone()).unwrap(), "{line}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recipes_are_checked_against_each_image() {
+        let registry = Registry::builtin();
+        let steps = ["blur 2", "crop 100 100 50 50"].map(|line| {
+            registry
+                .parse(&line.split_whitespace().collect::<Vec<_>>())
+                .unwrap()
+        });
+        let shape = |width, height| Shape {
+            width,
+            height,
+            colour: ColorType::Rgb8,
+        };
+        assert_eq!(check_recipe(&steps, shape(200, 200), true), Ok(()));
+        assert!(check_recipe(&steps, shape(80, 80), false).is_err());
+
+        let costly = registry
+            .parse(&["denoise", "median", "--radius", "50"])
+            .unwrap();
+        assert!(costly.check_cost().is_err());
+    }
+}
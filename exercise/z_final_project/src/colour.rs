@@ -0,0 +1,79 @@
+use image::Rgba;
+
+/// Parse a colour from the command line.
+///
+/// Accepts `#rgb`, `#rrggbb` and `#rrggbbaa` hex notation (the `#` is optional) or one of a few
+/// common colour names such as `white` or `transparent`.
+pub fn parse_colour(s: &str) -> Result<Rgba<u8>, String> {
+    let named = match s.to_ascii_lowercase().as_str() {
+        "black" => Some([0, 0, 0, 255]),
+        "white" => Some([255, 255, 255, 255]),
+        "red" => Some([255, 0, 0, 255]),
+        "green" => Some([0, 255, 0, 255]),
+        "blue" => Some([0, 0, 255, 255]),
+        "yellow" => Some([255, 255, 0, 255]),
+        "cyan" => Some([0, 255, 255, 255]),
+        "magenta" => Some([255, 0, 255, 255]),
+        "gray" | "grey" => Some([128, 128, 128, 255]),
+        "transparent" => Some([0, 0, 0, 0]),
+        _ => None,
+    };
+    if let Some(channels) = named {
+        return Ok(Rgba(channels));
+    }
+
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("'{s}' is not a colour name or hex colour"));
+    }
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).unwrap() as u8).collect();
+
+    let channels = match digits.len() {
+        3 => [digits[0] * 17, digits[1] * 17, digits[2] * 17, 255],
+        6 | 8 => {
+            let mut channels = [255; 4];
+            for (channel, pair) in channels.iter_mut().zip(digits.chunks(2)) {
+                *channel = pair[0] * 16 + pair[1];
+            }
+            channels
+        }
+        _ => return Err(format!("'{s}' should have 3, 6 or 8 hex digits")),
+    };
+
+    Ok(Rgba(channels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(parse_colour("white"), Ok(Rgba([255, 255, 255, 255])));
+        assert_eq!(parse_colour("Grey"), Ok(Rgba([128, 128, 128, 255])));
+        assert_eq!(parse_colour("transparent"), Ok(Rgba([0, 0, 0, 0])));
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_colour("#f80"), Ok(Rgba([255, 136, 0, 255])));
+        assert_eq!(parse_colour("1d2b53"), Ok(Rgba([29, 43, 83, 255])));
+        assert_eq!(parse_colour("#FFEC2780"), Ok(Rgba([255, 236, 39, 128])));
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            "",
+            "#",
+            "#12",
+            "#12345",
+            "#1234567",
+            "#ggg",
+            "mauve",
+            "#ff00ff00ff",
+        ] {
+            assert!(parse_colour(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use image::{DynamicImage, Rgba};
+
+use crate::colour::parse_colour;
+use crate::depth::Depth;
+use crate::linear;
+
+/// Rec. 709 luma weights, the same ones the image crate uses for `grayscale`.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// A 4x5 colour matrix, in the same layout as SVG's `feColorMatrix`.
+///
+/// Each row works out one output channel (red, green, blue, alpha) as a weighted sum of the
+/// input red, green, blue and alpha, plus an offset in the last column.  Channels are in
+/// 0.0-1.0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColourMatrix(pub [[f32; 5]; 4]);
+
+impl ColourMatrix {
+    /// Remove all colour, like the `grayscale` operation.
+    pub fn grayscale() -> Self {
+        let [r, g, b] = LUMA;
+        ColourMatrix([
+            [r, g, b, 0.0, 0.0],
+            [r, g, b, 0.0, 0.0],
+            [r, g, b, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// Make a negative, like the `invert` operation.  Alpha is left alone.
+    pub fn invert() -> Self {
+        ColourMatrix([
+            [-1.0, 0.0, 0.0, 0.0, 1.0],
+            [0.0, -1.0, 0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// Old photo brown tones.  `amount` goes from 0.0 (no change) to 1.0 (full sepia), using the
+    /// CSS `sepia()` filter values.
+    pub fn sepia(amount: f32) -> Self {
+        let k = 1.0 - amount.clamp(0.0, 1.0);
+        ColourMatrix([
+            [
+                0.393 + 0.607 * k,
+                0.769 - 0.769 * k,
+                0.189 - 0.189 * k,
+                0.0,
+                0.0,
+            ],
+            [
+                0.349 - 0.349 * k,
+                0.686 + 0.314 * k,
+                0.168 - 0.168 * k,
+                0.0,
+                0.0,
+            ],
+            [
+                0.272 - 0.272 * k,
+                0.534 - 0.534 * k,
+                0.131 + 0.869 * k,
+                0.0,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// Map dark areas to `dark` and light areas to `light`, with a smooth blend in between.
+    pub fn duotone(dark: Rgba<u8>, light: Rgba<u8>) -> Self {
+        let mut rows = [[0.0; 5]; 4];
+        for c in 0..3 {
+            let dark_c = dark[c] as f32 / 255.0;
+            let light_c = light[c] as f32 / 255.0;
+            for (i, weight) in LUMA.iter().enumerate() {
+                rows[c][i] = weight * (light_c - dark_c);
+            }
+            rows[c][4] = dark_c;
+        }
+        rows[3][3] = 1.0;
+        ColourMatrix(rows)
+    }
+
+    /// A cross-processed film look: warm, punchy highlights with flat, blue-tinted shadows.
+    pub fn cross_process() -> Self {
+        ColourMatrix([
+            [1.2, 0.05, -0.1, 0.0, -0.06],
+            [0.0, 1.15, 0.0, 0.0, -0.04],
+            [-0.05, 0.0, 0.7, 0.0, 0.16],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// A channel mixer: each output channel is a weighted mix of the input red, green and blue.
+    pub fn channel_mixer(red: [f32; 3], green: [f32; 3], blue: [f32; 3]) -> Self {
+        let row = |[r, g, b]: [f32; 3]| [r, g, b, 0.0, 0.0];
+        ColourMatrix([row(red), row(green), row(blue), [0.0, 0.0, 0.0, 1.0, 0.0]])
+    }
+
+    /// Apply the matrix to one pixel.
+    pub fn transform(&self, pixel: [f32; 4]) -> [f32; 4] {
+        self.0.map(|row| {
+            let sum: f32 = (0..4).map(|k| row[k] * pixel[k]).sum();
+            (sum + row[4]).clamp(0.0, 1.0)
+        })
+    }
+}
+
+/// Parse one filter for the `colour-matrix` operation.
+///
+/// One of `grayscale`, `invert`, `sepia[:AMOUNT]`, `duotone:DARK:LIGHT`, `cross-process`,
+/// `mixer:RR,RG,RB:GR,GG,GB:BR,BG,BB`, or a raw matrix of 20 comma separated numbers.
+pub fn parse_filter(s: &str) -> Result<ColourMatrix, String> {
+    let mut parts = s.split(':');
+    let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+    let args: Vec<&str> = parts.collect();
+    let no_args = |matrix: ColourMatrix| {
+        if args.is_empty() {
+            Ok(matrix)
+        } else {
+            Err(format!("'{name}' doesn't take any arguments"))
+        }
+    };
+
+    match name.as_str() {
+        "grayscale" | "greyscale" => no_args(ColourMatrix::grayscale()),
+        "invert" => no_args(ColourMatrix::invert()),
+        "cross-process" => no_args(ColourMatrix::cross_process()),
+        "sepia" => match args[..] {
+            [] => Ok(ColourMatrix::sepia(1.0)),
+            [amount] => Ok(ColourMatrix::sepia(parse_number(amount)?)),
+            _ => Err("use sepia or sepia:AMOUNT".to_string()),
+        },
+        "duotone" => match args[..] {
+            [dark, light] => Ok(ColourMatrix::duotone(
+                parse_colour(dark)?,
+                parse_colour(light)?,
+            )),
+            _ => Err("use duotone:DARK:LIGHT, e.g. duotone:#1d2b53:#ffec27".to_string()),
+        },
+        "mixer" => match args[..] {
+            [red, green, blue] => Ok(ColourMatrix::channel_mixer(
+                parse_weights(red)?,
+                parse_weights(green)?,
+                parse_weights(blue)?,
+            )),
+            _ => Err("use mixer:RR,RG,RB:GR,GG,GB:BR,BG,BB".to_string()),
+        },
+        _ if s.contains(',') => {
+            let numbers = s
+                .split(',')
+                .map(parse_number)
+                .collect::<Result<Vec<f32>, String>>()?;
+            if numbers.len() != 20 {
+                return Err(format!("a matrix needs 20 numbers, not {}", numbers.len()));
+            }
+            let mut rows = [[0.0; 5]; 4];
+            for (row, values) in rows.iter_mut().zip(numbers.chunks(5)) {
+                row.copy_from_slice(values);
+            }
+            Ok(ColourMatrix(rows))
+        }
+        _ => Err(format!("'{s}' is not a known colour filter")),
+    }
+}
+
+fn parse_number(s: &str) -> Result<f32, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("'{s}' is not a number"))
+}
+
+fn parse_weights(s: &str) -> Result<[f32; 3], String> {
+    let weights = s
+        .split(',')
+        .map(parse_number)
+        .collect::<Result<Vec<f32>, String>>()?;
+    weights
+        .try_into()
+        .map_err(|_| format!("'{s}' should be three comma separated weights"))
+}
+
+/// Run the image through a chain of **colour matrices**, one after another.
+///
+/// Each matrix's result is clamped to 0.0-1.0 before the next one sees it, so a chain gives the
+/// same result as a `colour-matrix` operation per filter.  The matrices work on sRGB values, so
+/// linear light (float) images are encoded for the duration and decoded again afterwards.
+pub fn colour_matrix(img: DynamicImage, filters: &[ColourMatrix]) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let mut rgba = depth.working_copy(img);
+
+    for pixel in rgba.pixels_mut() {
+        let mut input = pixel.0;
+        if depth == Depth::Float {
+            for value in &mut input[..3] {
+                *value = linear::linear_to_srgb(*value);
+            }
+        }
+        let mut output = filters
+            .iter()
+            .fold(input, |pixel, filter| filter.transform(pixel));
+        if depth == Depth::Float {
+            for value in &mut output[..3] {
+                *value = linear::srgb_to_linear(*value);
+            }
+        }
+        *pixel = Rgba(output);
+    }
+
+    depth.restore(rgba, had_alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(filter: &str, pixel: [f32; 4]) -> [f32; 4] {
+        parse_filter(filter).unwrap().transform(pixel)
+    }
+
+    #[test]
+    fn named_filters() {
+        assert_eq!(
+            apply("invert", [0.25, 0.5, 1.0, 0.5]),
+            [0.75, 0.5, 0.0, 0.5]
+        );
+        let [r, g, b, a] = apply("GREYSCALE", [1.0, 0.0, 0.0, 1.0]);
+        assert!((r - LUMA[0]).abs() < 1e-6 && r == g && g == b && a == 1.0);
+        assert_eq!(apply("sepia:0", [0.1, 0.2, 0.3, 1.0]), [0.1, 0.2, 0.3, 1.0]);
+        assert!(parse_filter("sepia").is_ok());
+        assert!(parse_filter("cross-process").is_ok());
+    }
+
+    #[test]
+    fn filters_with_arguments() {
+        assert_eq!(apply("duotone:black:white", [1.0, 1.0, 1.0, 1.0]), [1.0; 4]);
+        assert_eq!(
+            apply("mixer:0,0,1:0,1,0:1,0,0", [0.1, 0.2, 0.3, 1.0]),
+            [0.3, 0.2, 0.1, 1.0]
+        );
+        let swap = "0,1,0,0,0, 1,0,0,0,0, 0,0,1,0,0, 0,0,0,1,0";
+        assert_eq!(apply(swap, [0.1, 0.2, 0.3, 1.0]), [0.2, 0.1, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn filters_are_clamped_one_at_a_time() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(8, 8, |x, y| {
+            image::Rgb([x as u8 * 30, y as u8 * 30, 200])
+        }));
+        let filters = [
+            "sepia",
+            "mixer:2,0,0:0,2,0:0,0,2",
+            "mixer:0.5,0,0:0,0.5,0:0,0,0.5",
+        ]
+        .map(|filter| parse_filter(filter).unwrap());
+
+        let chained = colour_matrix(img.clone(), &filters);
+        let one_by_one = filters
+            .iter()
+            .fold(img.clone(), |img, filter| colour_matrix(img, &[*filter]));
+        assert_eq!(chained, one_by_one);
+
+        // doubling clips the blue, so halving it again doesn't give back the original
+        let blue = chained.into_rgb8().get_pixel(7, 7)[2];
+        assert!((127..=128).contains(&blue), "{blue}");
+    }
+
+    #[test]
+    fn bad_filters() {
+        for bad in [
+            "",
+            "blurry",
+            "invert:1",
+            "sepia:a",
+            "sepia:1:2",
+            "duotone:black",
+            "duotone:black:nope",
+            "mixer:1,0,0:0,1,0",
+            "mixer:1,0:0,1,0:0,0,1",
+            "1,2,3",
+        ] {
+            assert!(parse_filter(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use image::{DynamicImage, Rgb, RgbImage, Rgba, Rgba32FImage};
+
+use crate::depth::Depth;
+
+/// How different two images are.
+pub struct Metrics {
+    /// mean squared error over the red, green, blue and alpha channels, on a 0-255 scale (0
+    /// means identical)
+    pub mse: f64,
+    /// peak signal-to-noise ratio in dB (infinite when the images are identical)
+    pub psnr: f64,
+    /// mean structural similarity of the luma channel, averaged with that of the alpha channel
+    /// if either image has one (1.0 means identical)
+    pub ssim: f64,
+}
+
+/// **Compare** two images of the same size and measure how different they are.
+///
+/// Images are compared as RGBA at full precision, so differences in alpha or below 8 bits
+/// count.  Linear light (float) images are compared in sRGB, like the others.
+pub fn compare(a: &DynamicImage, b: &DynamicImage) -> Result<Metrics, String> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(format!(
+            "images are different sizes: {}x{} vs {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        ));
+    }
+
+    let has_alpha = a.color().has_alpha() || b.color().has_alpha();
+    let a = srgb_rgba(a);
+    let b = srgb_rgba(b);
+
+    let squared_error: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&pa, &pb)| ((pa as f64 - pb as f64) * 255.0).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len().max(1) as f64;
+    let psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    };
+
+    let luma_of = |img: &Rgba32FImage| img.pixels().map(luma).collect::<Vec<_>>();
+    let alpha_of = |img: &Rgba32FImage| img.pixels().map(|p| p[3] * 255.0).collect::<Vec<_>>();
+    let size = (a.width() as usize, a.height() as usize);
+    let mut ssim = ssim(&luma_of(&a), &luma_of(&b), size);
+    if has_alpha {
+        ssim = (ssim + self::ssim(&alpha_of(&a), &alpha_of(&b), size)) / 2.0;
+    }
+
+    Ok(Metrics { mse, psnr, ssim })
+}
+
+/// An RGBA float copy of the image holding sRGB values, whatever its depth.
+fn srgb_rgba(img: &DynamicImage) -> Rgba32FImage {
+    Depth::Sixteen.working_copy(img.clone())
+}
+
+/// Build an image that highlights where `a` and `b` differ.
+///
+/// `a` is shown as a dim grayscale backdrop and every pixel whose largest channel difference
+/// (including alpha, on a 0-255 scale) is more than `fuzz` is painted red, brighter for bigger
+/// differences.
+pub fn diff_image(a: &DynamicImage, b: &DynamicImage, fuzz: u8) -> DynamicImage {
+    let a = srgb_rgba(a);
+    let b = srgb_rgba(b);
+
+    let diff = RgbImage::from_fn(a.width(), a.height(), |x, y| {
+        let pa = a.get_pixel(x, y);
+        let pb = b.get_pixel(x, y);
+        let backdrop = (luma(pa) * 0.3) as u8;
+
+        let difference = (0..4)
+            .map(|c| (pa[c] - pb[c]).abs() * 255.0)
+            .fold(0.0, f32::max);
+        if difference <= fuzz as f32 {
+            return Rgb([backdrop, backdrop, backdrop]);
+        }
+
+        let strength = 0.35 + 0.65 * difference.min(255.0) / 255.0;
+        let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * strength) as u8;
+        Rgb([mix(backdrop, 255), mix(backdrop, 0), mix(backdrop, 0)])
+    });
+
+    DynamicImage::ImageRgb8(diff)
+}
+
+/// Rec. 601 luma of a pixel, in 0.0-255.0.
+fn luma(pixel: &Rgba<f32>) -> f32 {
+    (0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2]) * 255.0
+}
+
+/// Mean structural similarity (Wang et al. 2004) of two same-sized channels with values in
+/// 0.0-255.0, using the usual 11x11 Gaussian window with a sigma of 1.5.
+fn ssim(la: &[f32], lb: &[f32], (width, height): (usize, usize)) -> f64 {
+    const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+
+    let product =
+        |x: &[f32], y: &[f32]| -> Vec<f32> { x.iter().zip(y).map(|(p, q)| p * q).collect() };
+
+    let kernel = gaussian_kernel(1.5, 5);
+    let mu_a = convolve(la, width, height, &kernel);
+    let mu_b = convolve(lb, width, height, &kernel);
+    let sq_a = convolve(&product(la, la), width, height, &kernel);
+    let sq_b = convolve(&product(lb, lb), width, height, &kernel);
+    let ab = convolve(&product(la, lb), width, height, &kernel);
+
+    let total: f64 = (0..width * height)
+        .map(|i| {
+            let var_a = sq_a[i] - mu_a[i] * mu_a[i];
+            let var_b = sq_b[i] - mu_b[i] * mu_b[i];
+            let covariance = ab[i] - mu_a[i] * mu_b[i];
+            let numerator = (2.0 * mu_a[i] * mu_b[i] + C1) * (2.0 * covariance + C2);
+            let denominator = (mu_a[i] * mu_a[i] + mu_b[i] * mu_b[i] + C1) * (var_a + var_b + C2);
+            (numerator / denominator) as f64
+        })
+        .sum();
+
+    total / (width * height) as f64
+}
+
+/// A normalised 1D Gaussian kernel reaching `radius` pixels either side of the centre.
+fn gaussian_kernel(sigma: f32, radius: usize) -> Vec<f32> {
+    let weights: Vec<f32> = (0..=2 * radius)
+        .map(|i| {
+            let d = i as f32 - radius as f32;
+            (-(d * d) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+/// Separable convolution of a single channel buffer, clamping at the edges.
+fn convolve(data: &[f32], width: usize, height: usize, kernel: &[f32]) -> Vec<f32> {
+    let radius = (kernel.len() / 2) as isize;
+    let sample = |i: isize, len: usize| i.clamp(0, len as isize - 1) as usize;
+
+    let mut horizontal = vec![0.0; data.len()];
+    for y in 0..height {
+        let row = &data[y * width..(y + 1) * width];
+        for x in 0..width {
+            horizontal[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, w)| w * row[sample(x as isize + k as isize - radius, width)])
+                .sum();
+        }
+    }
+
+    let mut out = vec![0.0; data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            out[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, w)| {
+                    w * horizontal[sample(y as isize + k as isize - radius, height) * width + x]
+                })
+                .sum();
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, RgbaImage};
+
+    fn gradient(alpha: impl Fn(u32, u32) -> u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([x as u8 * 8, y as u8 * 8, 128, alpha(x, y)])
+        }))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let img = gradient(|_, _| 255);
+        let metrics = compare(&img, &img).unwrap();
+        assert_eq!(metrics.mse, 0.0);
+        assert_eq!(metrics.psnr, f64::INFINITY);
+        assert!((metrics.ssim - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn alpha_differences_count() {
+        let opaque = gradient(|_, _| 255);
+        let faded = gradient(|x, _| 255 - x as u8 * 4);
+        let metrics = compare(&opaque, &faded).unwrap();
+        assert!(metrics.mse > 0.0);
+        assert!(metrics.ssim < 0.99);
+
+        let diff = diff_image(&opaque, &faded, 0).into_rgb8();
+        assert_eq!(diff.get_pixel(0, 0)[0], diff.get_pixel(0, 0)[1]);
+        assert!(diff.get_pixel(31, 0)[0] > diff.get_pixel(31, 0)[1]);
+    }
+
+    #[test]
+    fn sixteen_bit_differences_count() {
+        let image =
+            |red| DynamicImage::ImageRgb16(ImageBuffer::from_pixel(16, 16, Rgb([red, 1000, 1000])));
+        let metrics = compare(&image(1000), &image(1001)).unwrap();
+        assert!(metrics.mse > 0.0);
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        let small = DynamicImage::new_rgb8(4, 4);
+        let wide = DynamicImage::new_rgb8(5, 4);
+        assert!(compare(&small, &wide).is_err());
+    }
+
+    #[test]
+    fn ssim_of_flat_images() {
+        // with no variance, SSIM is just the luminance term
+        let (a, b) = (100.0f32, 140.0f32);
+        let c1 = (0.01f32 * 255.0).powi(2);
+        let expected = (2.0 * a * b + c1) / (a * a + b * b + c1);
+        let value = ssim(&[a; 64], &[b; 64], (8, 8));
+        assert!(
+            (value - expected as f64).abs() < 1e-4,
+            "{value} vs {expected}"
+        );
+        assert!((ssim(&[a; 64], &[a; 64], (8, 8)) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ssim_falls_with_noise() {
+        let clean = gradient(|_, _| 255);
+        let mut noisy = clean.to_rgba8();
+        for (i, pixel) in noisy.pixels_mut().enumerate() {
+            pixel[0] = pixel[0].wrapping_add((i * 37 % 64) as u8);
+        }
+        let metrics = compare(&clean, &DynamicImage::ImageRgba8(noisy)).unwrap();
+        assert!(metrics.ssim < 0.9 && metrics.ssim > 0.0, "{}", metrics.ssim);
+    }
+}
@@ -0,0 +1,222 @@
+use clap::ValueEnum;
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::depth::Depth;
+use crate::progress::Progress;
+
+/// Radius of the patches non-local means compares, so 3x3 patches.
+const PATCH_RADIUS: i64 = 1;
+
+/// Ways of removing noise while keeping edges.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DenoiseMethod {
+    /// the middle value of the square around each pixel; removes specks and salt-and-pepper
+    /// noise without softening edges
+    Median,
+    /// average nearby pixels of similar colour only, so edges stay sharp
+    Bilateral,
+    /// non-local means: average pixels whose surroundings look alike; best for photographic
+    /// noise, but slow
+    NlMeans,
+}
+
+impl DenoiseMethod {
+    /// The radius used when none is given.
+    pub fn default_radius(self) -> u32 {
+        match self {
+            DenoiseMethod::Median => 1,
+            DenoiseMethod::Bilateral => 3,
+            DenoiseMethod::NlMeans => 5,
+        }
+    }
+
+    /// How far away (in pixels) denoising with `radius` can take colours from.
+    pub fn reach(self, radius: u32) -> u32 {
+        match self {
+            DenoiseMethod::NlMeans => radius + PATCH_RADIUS as u32,
+            _ => radius,
+        }
+    }
+}
+
+/// **Denoise** the image.
+///
+/// `radius` is the size of the square looked at around each pixel (for non-local means, the
+/// square searched for similar patches).  `strength` is how different colours can be, as a
+/// fraction of the full range, and still be mixed together; it doesn't affect `Median`.
+///
+/// Grayscale images stay grayscale, and every image keeps its bit depth.
+pub fn denoise(
+    img: DynamicImage,
+    method: DenoiseMethod,
+    radius: u32,
+    strength: f32,
+) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let had_colour = img.color().has_color();
+    let source = depth.working_copy(img);
+
+    let radius = radius as i64;
+    let strength = strength.max(f32::EPSILON);
+    let mut progress = Progress::new("denoise", source.height() as u64);
+    let out = Rgba32FImage::from_fn(source.width(), source.height(), |x, y| {
+        if x == 0 {
+            progress.set(y as u64);
+        }
+        let (x, y) = (x as i64, y as i64);
+        match method {
+            DenoiseMethod::Median => median(&source, x, y, radius),
+            DenoiseMethod::Bilateral => bilateral(&source, x, y, radius, strength),
+            DenoiseMethod::NlMeans => nl_means(&source, x, y, radius, strength),
+        }
+    });
+
+    let img = depth.restore(out, had_alpha);
+    match (had_colour, depth, had_alpha) {
+        (true, ..) | (false, Depth::Float, _) => img,
+        (false, Depth::Eight, false) => DynamicImage::ImageLuma8(img.into_luma8()),
+        (false, Depth::Eight, true) => DynamicImage::ImageLumaA8(img.into_luma_alpha8()),
+        (false, Depth::Sixteen, false) => DynamicImage::ImageLuma16(img.into_luma16()),
+        (false, Depth::Sixteen, true) => DynamicImage::ImageLumaA16(img.into_luma_alpha16()),
+    }
+}
+
+/// The pixel at `(x, y)`, with coordinates outside the image clamped to its edges.
+fn pixel(img: &Rgba32FImage, x: i64, y: i64) -> &Rgba<f32> {
+    let x = x.clamp(0, img.width() as i64 - 1) as u32;
+    let y = y.clamp(0, img.height() as i64 - 1) as u32;
+    img.get_pixel(x, y)
+}
+
+/// Mean squared difference between the colour channels of two pixels, ignoring alpha.
+fn colour_distance(a: &Rgba<f32>, b: &Rgba<f32>) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum::<f32>() / 3.0
+}
+
+/// The median of each channel over the square of `radius` around `(x, y)`.
+fn median(img: &Rgba32FImage, x: i64, y: i64, radius: i64) -> Rgba<f32> {
+    let mut values = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+    Rgba([0, 1, 2, 3].map(|c| {
+        values.clear();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                values.push(pixel(img, x + dx, y + dy)[c]);
+            }
+        }
+        let middle = values.len() / 2;
+        *values.select_nth_unstable_by(middle, f32::total_cmp).1
+    }))
+}
+
+/// Average the square of `radius` around `(x, y)`, weighting pixels by how near they are and
+/// how close their colour is to that of the pixel at `(x, y)`.
+fn bilateral(img: &Rgba32FImage, x: i64, y: i64, radius: i64, strength: f32) -> Rgba<f32> {
+    let centre = pixel(img, x, y);
+    // the square reaches about two standard deviations of the distance weighting
+    let spatial = 2.0 * (radius as f32 / 2.0).max(0.5).powi(2);
+    let range = 2.0 * strength * strength;
+
+    let mut sum = [0.0; 4];
+    let mut total = 0.0;
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            let other = pixel(img, x + dx, y + dy);
+            let weight = (-((dx * dx + dy * dy) as f32) / spatial
+                - colour_distance(centre, other) / range)
+                .exp();
+            for c in 0..4 {
+                sum[c] += other[c] * weight;
+            }
+            total += weight;
+        }
+    }
+    Rgba(sum.map(|s| s / total))
+}
+
+/// Average the pixels in the square of `radius` around `(x, y)`, weighting each by how much
+/// the patch around it looks like the patch around `(x, y)`.
+fn nl_means(img: &Rgba32FImage, x: i64, y: i64, radius: i64, strength: f32) -> Rgba<f32> {
+    let patch_pixels = ((2 * PATCH_RADIUS + 1) * (2 * PATCH_RADIUS + 1)) as f32;
+    let h = strength * strength;
+
+    let mut sum = [0.0; 4];
+    let mut total = 0.0;
+    for sy in -radius..=radius {
+        for sx in -radius..=radius {
+            let mut distance = 0.0;
+            for py in -PATCH_RADIUS..=PATCH_RADIUS {
+                for px in -PATCH_RADIUS..=PATCH_RADIUS {
+                    distance += colour_distance(
+                        pixel(img, x + px, y + py),
+                        pixel(img, x + sx + px, y + sy + py),
+                    );
+                }
+            }
+            let weight = (-distance / patch_pixels / h).exp();
+            let other = pixel(img, x + sx, y + sy);
+            for c in 0..4 {
+                sum[c] += other[c] * weight;
+            }
+            total += weight;
+        }
+    }
+    Rgba(sum.map(|s| s / total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    use crate::Registry;
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_pixel(12, 9, Rgb([40, 150, 220])));
+        for method in [
+            DenoiseMethod::Median,
+            DenoiseMethod::Bilateral,
+            DenoiseMethod::NlMeans,
+        ] {
+            let radius = method.default_radius();
+            assert_eq!(
+                denoise(flat.clone(), method, radius, 0.1),
+                flat,
+                "{method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn median_removes_specks() {
+        let mut speckled = GrayImage::from_pixel(9, 9, Luma([100]));
+        speckled.put_pixel(4, 4, Luma([255]));
+        speckled.put_pixel(0, 8, Luma([0]));
+        let cleaned = denoise(
+            DynamicImage::ImageLuma8(speckled),
+            DenoiseMethod::Median,
+            1,
+            0.1,
+        );
+        assert_eq!(
+            cleaned,
+            DynamicImage::ImageLuma8(GrayImage::from_pixel(9, 9, Luma([100])))
+        );
+    }
+
+    #[test]
+    fn large_radii_are_too_costly_to_serve() {
+        let registry = Registry::builtin();
+        for (line, cheap) in [
+            ("denoise median --radius 10", true),
+            ("denoise median --radius 11", false),
+            ("denoise nl-means", true),
+            ("denoise bilateral --radius 50", false),
+        ] {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let step = registry.parse(&words).unwrap();
+            assert_eq!(step.check_cost().is_ok(), cheap, "{line}");
+        }
+    }
+}
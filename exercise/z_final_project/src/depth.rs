@@ -0,0 +1,107 @@
+use image::{ColorType, DynamicImage, Rgba32FImage};
+
+use crate::linear;
+
+/// How many bits each channel of an image has.  8 and 16-bit images are sRGB encoded, while
+/// float images hold linear light (see `linear`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Depth {
+    Eight,
+    Sixteen,
+    Float,
+}
+
+impl Depth {
+    pub fn of(img: &DynamicImage) -> Depth {
+        Depth::of_colour(img.color())
+    }
+
+    /// The depth of images of the colour type `colour`.
+    pub fn of_colour(colour: ColorType) -> Depth {
+        match colour {
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                Depth::Sixteen
+            }
+            ColorType::Rgb32F | ColorType::Rgba32F => Depth::Float,
+            _ => Depth::Eight,
+        }
+    }
+
+    /// Convert any image to an RGBA float working copy in the encoding used at this depth, so
+    /// it can be mixed with other images of this depth: linear light for float, sRGB otherwise.
+    pub fn working_copy(self, img: DynamicImage) -> Rgba32FImage {
+        match (self == Depth::Float, linear::is_linear(&img)) {
+            (true, false) => linear::to_linear(img).into_rgba32f(),
+            (false, true) => linear::to_srgb(img, true).into_rgba32f(),
+            _ => img.into_rgba32f(),
+        }
+    }
+
+    /// Turn a working copy back into an image of this depth, dropping alpha unless `alpha`.
+    pub fn restore(self, working: Rgba32FImage, alpha: bool) -> DynamicImage {
+        let img = DynamicImage::ImageRgba32F(working);
+        match (self, alpha) {
+            (Depth::Eight, false) => DynamicImage::ImageRgb8(img.into_rgb8()),
+            (Depth::Eight, true) => DynamicImage::ImageRgba8(img.into_rgba8()),
+            (Depth::Sixteen, false) => DynamicImage::ImageRgb16(img.into_rgb16()),
+            (Depth::Sixteen, true) => DynamicImage::ImageRgba16(img.into_rgba16()),
+            (Depth::Float, false) => DynamicImage::ImageRgb32F(img.into_rgb32f()),
+            (Depth::Float, true) => img,
+        }
+    }
+
+    /// The colour type `restore` gives, with or without `alpha`.
+    pub fn colour_type(self, alpha: bool) -> ColorType {
+        match (self, alpha) {
+            (Depth::Eight, false) => ColorType::Rgb8,
+            (Depth::Eight, true) => ColorType::Rgba8,
+            (Depth::Sixteen, false) => ColorType::Rgb16,
+            (Depth::Sixteen, true) => ColorType::Rgba16,
+            (Depth::Float, false) => ColorType::Rgb32F,
+            (Depth::Float, true) => ColorType::Rgba32F,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn sixteen_bits_survive_the_working_copy() {
+        // values that 8 bits can't hold
+        let img = DynamicImage::ImageRgba16(ImageBuffer::from_fn(8, 8, |x, y| {
+            Rgba([1001 + x as u16, 40_000 + y as u16, 65_534, 32_769])
+        }));
+        let depth = Depth::of(&img);
+        assert_eq!(depth, Depth::Sixteen);
+        let restored = depth.restore(depth.working_copy(img.clone()), true);
+        assert_eq!(restored, img);
+    }
+
+    #[test]
+    fn float_images_stay_linear() {
+        let img =
+            DynamicImage::ImageRgba32F(ImageBuffer::from_pixel(2, 2, Rgba([0.01, 0.5, 2.0, 1.0])));
+        let restored = Depth::Float.restore(Depth::Float.working_copy(img.clone()), true);
+        assert_eq!(restored, img);
+
+        // mixing with a float image decodes sRGB to linear light
+        let grey = DynamicImage::new_rgb8(1, 1).brighten(128);
+        let working = Depth::Float.working_copy(grey);
+        assert!((working.get_pixel(0, 0)[0] - linear::srgb_to_linear(128.0 / 255.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn colour_types_match_restore() {
+        let working = Rgba32FImage::new(1, 1);
+        for depth in [Depth::Eight, Depth::Sixteen, Depth::Float] {
+            for alpha in [false, true] {
+                let restored = depth.restore(working.clone(), alpha);
+                assert_eq!(restored.color(), depth.colour_type(alpha));
+                assert_eq!(Depth::of_colour(restored.color()), depth);
+            }
+        }
+    }
+}
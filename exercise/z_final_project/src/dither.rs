@@ -0,0 +1,326 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+
+use crate::palette::{nearest, to_f32, to_rgb};
+
+/// Side length of the generated blue noise threshold texture.
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Ways of dithering an image down to a fixed palette.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DitherMethod {
+    /// error diffusion spreading all of the error to four neighbours
+    FloydSteinberg,
+    /// error diffusion spreading 3/4 of the error, for crisper, higher contrast results
+    Atkinson,
+    /// ordered dithering with an 8x8 Bayer matrix (a regular cross-hatch pattern)
+    Bayer,
+    /// ordered dithering with a blue noise texture (an even, pattern-free grain)
+    BlueNoise,
+}
+
+/// The built-in palette called `spec`, if there is one.
+pub fn builtin_palette(spec: &str) -> Option<Vec<Rgb<u8>>> {
+    let colours: &[[u8; 3]] = match spec.to_ascii_lowercase().as_str() {
+        "bw" => &[[0, 0, 0], [255, 255, 255]],
+        "gray4" | "grey4" => &[[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]],
+        "gameboy" => &[[15, 56, 15], [48, 98, 48], [139, 172, 15], [155, 188, 15]],
+        "cga" => &[[0, 0, 0], [85, 255, 255], [255, 85, 255], [255, 255, 255]],
+        _ => return None,
+    };
+    Some(colours.iter().map(|c| Rgb(*c)).collect())
+}
+
+/// Resolve a palette given on the command line.
+///
+/// This is either the name of a built-in palette (`bw`, `gray4`, `gameboy` or `cga`) or a file:
+/// an image (every distinct colour in it is used, so the `palette --swatch` output works), a
+/// GIMP `.gpl` palette, or a text file with one hex colour per line.
+pub fn load_palette(spec: &str) -> Result<Vec<Rgb<u8>>, String> {
+    if let Some(colours) = builtin_palette(spec) {
+        return Ok(colours);
+    }
+
+    let colours = if ImageFormat::from_path(spec).is_ok() {
+        let img = image::open(spec).map_err(|e| format!("{spec}: {e}"))?;
+        let mut colours: Vec<Rgb<u8>> = Vec::new();
+        for pixel in img.to_rgb8().pixels() {
+            if !colours.contains(pixel) {
+                if colours.len() == 256 {
+                    return Err(format!("{spec}: has more than 256 colours"));
+                }
+                colours.push(*pixel);
+            }
+        }
+        colours
+    } else {
+        let text = std::fs::read_to_string(spec).map_err(|e| format!("{spec}: {e}"))?;
+        parse_palette_text(&text).map_err(|e| format!("{spec}: {e}"))?
+    };
+
+    if colours.is_empty() {
+        return Err(format!("{spec}: no colours found"));
+    }
+    Ok(colours)
+}
+
+/// Parse a GIMP `.gpl` palette or a list of hex colours.  Comments and headers are skipped.
+fn parse_palette_text(text: &str) -> Result<Vec<Rgb<u8>>, String> {
+    let mut colours = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Vec<u8> = words
+            .iter()
+            .take(3)
+            .filter_map(|w| w.parse().ok())
+            .collect();
+
+        if let [r, g, b] = numbers[..] {
+            colours.push(Rgb([r, g, b]));
+        } else if let Ok(colour) = crate::colour::parse_colour(words[0]) {
+            colours.push(Rgb([colour[0], colour[1], colour[2]]));
+        } else if !(line.starts_with('#')
+            || line.starts_with(';')
+            || line.starts_with("GIMP Palette")
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:"))
+        {
+            return Err(format!("can't read a colour from '{line}'"));
+        }
+    }
+    Ok(colours)
+}
+
+/// **Dither** the image so that it only uses colours from `palette`.
+pub fn dither(img: DynamicImage, palette: &[Rgb<u8>], method: DitherMethod) -> DynamicImage {
+    let palette: Vec<[f32; 3]> = palette.iter().map(|c| to_f32(*c)).collect();
+    let rgb = img.into_rgb8();
+
+    let dithered = match method {
+        DitherMethod::FloydSteinberg => diffuse(
+            &rgb,
+            &palette,
+            &[
+                (1, 0, 7.0 / 16.0),
+                (-1, 1, 3.0 / 16.0),
+                (0, 1, 5.0 / 16.0),
+                (1, 1, 1.0 / 16.0),
+            ],
+        ),
+        DitherMethod::Atkinson => diffuse(
+            &rgb,
+            &palette,
+            &[
+                (1, 0, 1.0 / 8.0),
+                (2, 0, 1.0 / 8.0),
+                (-1, 1, 1.0 / 8.0),
+                (0, 1, 1.0 / 8.0),
+                (1, 1, 1.0 / 8.0),
+                (0, 2, 1.0 / 8.0),
+            ],
+        ),
+        DitherMethod::Bayer => ordered(&rgb, &palette, &bayer_matrix(), 8),
+        DitherMethod::BlueNoise => ordered(&rgb, &palette, blue_noise(), BLUE_NOISE_SIZE),
+    };
+
+    DynamicImage::ImageRgb8(dithered)
+}
+
+/// Error diffusion: snap each pixel to the palette and push the error onto the pixels that
+/// haven't been visited yet, weighted by `kernel` entries of (dx, dy, weight).
+fn diffuse(img: &RgbImage, palette: &[[f32; 3]], kernel: &[(i64, i64, f32)]) -> RgbImage {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    let mut buffer: Vec<[f32; 3]> = img.pixels().map(|p| to_f32(*p)).collect();
+    let mut out = RgbImage::new(img.width(), img.height());
+
+    for y in 0..height {
+        for x in 0..width {
+            let old = buffer[(y * width + x) as usize];
+            let new = palette[nearest(palette, old)];
+            out.put_pixel(x as u32, y as u32, to_rgb(new));
+
+            let error = [0, 1, 2].map(|c| old[c] - new[c]);
+            for &(dx, dy, weight) in kernel {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= width || ny >= height {
+                    continue;
+                }
+                let neighbour = &mut buffer[(ny * width + nx) as usize];
+                for c in 0..3 {
+                    neighbour[c] += error[c] * weight;
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Ordered dithering: nudge each pixel by a tiled threshold (in 0.0-1.0) before snapping it to
+/// the palette.  The nudge is scaled to roughly the gap between palette colours.
+fn ordered(img: &RgbImage, palette: &[[f32; 3]], thresholds: &[f32], size: usize) -> RgbImage {
+    let spread = 255.0 / (palette.len().max(2) - 1) as f32;
+
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let threshold = thresholds[(y as usize % size) * size + x as usize % size];
+        let offset = (threshold - 0.5) * spread;
+        let colour = to_f32(*img.get_pixel(x, y)).map(|c| c + offset);
+        to_rgb(palette[nearest(palette, colour)])
+    })
+}
+
+/// The classic 8x8 Bayer threshold matrix, normalised to 0.0-1.0.
+fn bayer_matrix() -> Vec<f32> {
+    let mut matrix = vec![0u32];
+    let mut size = 1;
+    while size < 8 {
+        let mut next = vec![0; 4 * size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let v = 4 * matrix[y * size + x];
+                next[y * 2 * size + x] = v;
+                next[y * 2 * size + x + size] = v + 2;
+                next[(y + size) * 2 * size + x] = v + 3;
+                next[(y + size) * 2 * size + x + size] = v + 1;
+            }
+        }
+        matrix = next;
+        size *= 2;
+    }
+    matrix.iter().map(|&v| (v as f32 + 0.5) / 64.0).collect()
+}
+
+/// A tileable blue noise threshold texture, generated once with the void-and-cluster method.
+fn blue_noise() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+    TEXTURE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+/// Ulichney's void-and-cluster algorithm: rank every pixel of a `size`x`size` toroidal grid so
+/// that the pixels switched on at any rank are spread as evenly as possible.
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let n = size * size;
+
+    // energy contributed by an "on" pixel to every offset around it, wrapping at the edges
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let (dx, dy) = (i % size, i / size);
+            let dx = dx.min(size - dx) as f32;
+            let dy = dy.min(size - dy) as f32;
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let mut energy = vec![0.0f32; n];
+    let update = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |on: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| on[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |on: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| !on[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // start with a sparse random pattern (from a fixed seed so the texture never changes)
+    let mut on = vec![false; n];
+    let mut seed: u32 = 0x9e37_79b9;
+    let initial = n / 10;
+    let mut count = 0;
+    while count < initial {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let p = seed as usize % n;
+        if !on[p] {
+            on[p] = true;
+            update(&mut energy, p, 1.0);
+            count += 1;
+        }
+    }
+
+    // even it out by moving the tightest cluster into the largest void until it settles
+    loop {
+        let cluster = tightest_cluster(&on, &energy);
+        on[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+        let void = largest_void(&on, &energy);
+        on[void] = true;
+        update(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0usize; n];
+
+    // rank the initial pattern by taking away the tightest clusters one at a time
+    let mut pattern = on.clone();
+    let mut pattern_energy = energy.clone();
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&pattern, &pattern_energy);
+        pattern[cluster] = false;
+        update(&mut pattern_energy, cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // then rank everything else by filling in the largest voids
+    for r in initial..n {
+        let void = largest_void(&on, &energy);
+        on[void] = true;
+        update(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [DitherMethod; 4] = [
+        DitherMethod::FloydSteinberg,
+        DitherMethod::Atkinson,
+        DitherMethod::Bayer,
+        DitherMethod::BlueNoise,
+    ];
+
+    #[test]
+    fn only_palette_colours_are_used() {
+        let gradient = DynamicImage::ImageRgb8(RgbImage::from_fn(48, 32, |x, y| {
+            Rgb([x as u8 * 5, y as u8 * 8, 200 - x as u8 * 4])
+        }));
+        let palette = builtin_palette("cga").unwrap();
+        for method in METHODS {
+            let dithered = dither(gradient.clone(), &palette, method).into_rgb8();
+            for pixel in dithered.pixels() {
+                assert!(palette.contains(pixel), "{method:?} used {pixel:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn middle_grey_is_half_white() {
+        let grey = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 64, Rgb([128, 128, 128])));
+        let palette = builtin_palette("bw").unwrap();
+        for method in METHODS {
+            let dithered = dither(grey.clone(), &palette, method).into_rgb8();
+            let white = dithered.pixels().filter(|pixel| pixel[0] == 255).count();
+            let fraction = white as f32 / (64.0 * 64.0);
+            assert!((0.45..=0.55).contains(&fraction), "{method:?}: {fraction}");
+        }
+    }
+}
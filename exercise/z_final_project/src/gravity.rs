@@ -0,0 +1,81 @@
+use clap::ValueEnum;
+
+/// Anchor point used to position one thing (an overlay, some text...) on an image.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gravity {
+    /// top left corner
+    NorthWest,
+    /// top edge, centred horizontally
+    North,
+    /// top right corner
+    NorthEast,
+    /// left edge, centred vertically
+    West,
+    /// centre of the image
+    Center,
+    /// right edge, centred vertically
+    East,
+    /// bottom left corner
+    SouthWest,
+    /// bottom edge, centred horizontally
+    South,
+    /// bottom right corner
+    SouthEast,
+}
+
+impl Gravity {
+    /// Work out the top left position of an `item` sized box placed on a `canvas` sized box.
+    ///
+    /// Offsets push the item *inwards* from the gravity edge, so `south-east` with an offset of
+    /// (10, 10) leaves a 10 pixel margin at the bottom right.  For centred axes a positive offset
+    /// moves right/down.
+    pub fn position(self, canvas: (u32, u32), item: (u32, u32), offset: (i64, i64)) -> (i64, i64) {
+        let (canvas_w, canvas_h) = (canvas.0 as i64, canvas.1 as i64);
+        let (item_w, item_h) = (item.0 as i64, item.1 as i64);
+        let (dx, dy) = offset;
+
+        let x = match self {
+            Gravity::NorthWest | Gravity::West | Gravity::SouthWest => dx,
+            Gravity::North | Gravity::Center | Gravity::South => (canvas_w - item_w) / 2 + dx,
+            Gravity::NorthEast | Gravity::East | Gravity::SouthEast => canvas_w - item_w - dx,
+        };
+        let y = match self {
+            Gravity::NorthWest | Gravity::North | Gravity::NorthEast => dy,
+            Gravity::West | Gravity::Center | Gravity::East => (canvas_h - item_h) / 2 + dy,
+            Gravity::SouthWest | Gravity::South | Gravity::SouthEast => canvas_h - item_h - dy,
+        };
+
+        (x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_push_inwards() {
+        let place = |gravity: Gravity| gravity.position((100, 50), (20, 10), (3, 4));
+        assert_eq!(place(Gravity::NorthWest), (3, 4));
+        assert_eq!(place(Gravity::North), (43, 4));
+        assert_eq!(place(Gravity::NorthEast), (77, 4));
+        assert_eq!(place(Gravity::West), (3, 24));
+        assert_eq!(place(Gravity::Center), (43, 24));
+        assert_eq!(place(Gravity::East), (77, 24));
+        assert_eq!(place(Gravity::SouthWest), (3, 36));
+        assert_eq!(place(Gravity::South), (43, 36));
+        assert_eq!(place(Gravity::SouthEast), (77, 36));
+    }
+
+    #[test]
+    fn items_larger_than_the_canvas_hang_over_the_edges() {
+        assert_eq!(
+            Gravity::Center.position((10, 10), (20, 30), (0, 0)),
+            (-5, -10)
+        );
+        assert_eq!(
+            Gravity::SouthEast.position((10, 10), (20, 30), (0, 0)),
+            (-10, -20)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+
+/// Perceptual hash algorithms.  All of them produce 64 bit hashes.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// average hash: which pixels are brighter than the mean
+    Ahash,
+    /// difference hash: which pixels are brighter than their right-hand neighbour
+    Dhash,
+    /// perceptual hash: the signs of the low frequency DCT coefficients
+    Phash,
+}
+
+/// **Hash** the image so that visually similar images get similar hashes.
+pub fn image_hash(img: &DynamicImage, algorithm: HashAlgorithm) -> u64 {
+    match algorithm {
+        HashAlgorithm::Ahash => ahash(img),
+        HashAlgorithm::Dhash => dhash(img),
+        HashAlgorithm::Phash => phash(img),
+    }
+}
+
+/// Number of bits that differ between two hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Group images whose hashes are within `threshold` bits of each other.
+///
+/// Grouping is transitive: if A is close to B and B is close to C then all three end up in the
+/// same group.  Only groups with more than one member are returned.
+pub fn group_similar(hashes: &[(PathBuf, u64)], threshold: u32) -> Vec<Vec<PathBuf>> {
+    // a tiny union-find over the indices of `hashes`
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if hamming_distance(hashes[i].1, hashes[j].1) <= threshold {
+                let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+                parent[rj] = ri;
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<PathBuf>> = vec![Vec::new(); hashes.len()];
+    for (i, (path, _)) in hashes.iter().enumerate() {
+        let r = root(&mut parent, i);
+        groups[r].push(path.clone());
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Find every file under `dir` that looks like an image, going into subdirectories if
+/// `recursive` is set.
+pub fn image_files(dir: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                files.extend(image_files(&path, recursive)?);
+            }
+        } else if image::ImageFormat::from_path(&path).is_ok() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Shrink the image to a tiny grayscale thumbnail, throwing away detail and colour.
+fn thumbnail(img: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    img.resize_exact(width, height, FilterType::Triangle)
+        .into_luma8()
+}
+
+fn ahash(img: &DynamicImage) -> u64 {
+    let small = thumbnail(img, 8, 8);
+    let mean = small.pixels().map(|p| p[0] as u32).sum::<u32>() / 64;
+    bits(small.pixels().map(|p| p[0] as u32 > mean))
+}
+
+fn dhash(img: &DynamicImage) -> u64 {
+    let small = thumbnail(img, 9, 8);
+    bits((0..8).flat_map(|y| {
+        let small = &small;
+        (0..8).map(move |x| small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0])
+    }))
+}
+
+fn phash(img: &DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    let small = thumbnail(img, SIZE as u32, SIZE as u32);
+    let pixels: Vec<f32> = small.pixels().map(|p| p[0] as f32).collect();
+
+    // 2D DCT-II, but we only need the 8x8 lowest frequencies
+    let cosines: Vec<f32> = (0..8 * SIZE)
+        .map(|i| {
+            let (u, x) = (i / SIZE, i % SIZE);
+            ((2 * x + 1) as f32 * u as f32 * PI / (2 * SIZE) as f32).cos()
+        })
+        .collect();
+    let mut coefficients = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    sum += pixels[y * SIZE + x] * cosines[u * SIZE + x] * cosines[v * SIZE + y];
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    // the DC term is just the average brightness, so leave it out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    bits(coefficients.iter().map(|&c| c > median))
+}
+
+/// Pack up to 64 booleans into a hash, first one in the most significant bit.
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(7, 7), 0);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    fn groups_are_transitive() {
+        let hashes: Vec<(PathBuf, u64)> =
+            [("a", 0b0000), ("b", 0b0011), ("c", 0b1111), ("d", 0xff00)]
+                .into_iter()
+                .map(|(name, hash)| (PathBuf::from(name), hash))
+                .collect();
+        // a is two bits from b and b two from c, but a and c are four apart; d is on its own
+        let groups = group_similar(&hashes, 2);
+        assert_eq!(groups, [["a", "b", "c"].map(PathBuf::from)]);
+        assert!(group_similar(&hashes, 1).is_empty());
+    }
+}
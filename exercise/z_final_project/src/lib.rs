@@ -0,0 +1,41 @@
+//! mirage: image operations and the command line tool built on them.
+//!
+//! Operations are `ImageOp`s looked up in a `Registry`.  A crate of in-house operations can
+//! register its own alongside the built-in ones and run the same command line with
+//! `cli::run`.
+
+mod alpha;
+mod blur;
+pub mod cli;
+mod colour;
+mod colour_matrix;
+mod compare;
+mod denoise;
+mod depth;
+mod dither;
+mod gravity;
+mod hash;
+mod linear;
+mod mask;
+mod montage;
+mod output;
+mod overlay;
+mod palette;
+mod preview;
+mod progress;
+mod recipe;
+pub mod registry;
+mod seamless;
+mod serve;
+mod session;
+mod shell;
+mod text;
+mod texture;
+mod tiled;
+mod watch;
+
+pub use registry::{Action, ImageOp, Registry, Shape, Step};
+
+// public only so that benches/blur.rs can time it
+#[doc(hidden)]
+pub use blur::fast_gaussian;
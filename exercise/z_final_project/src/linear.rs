@@ -0,0 +1,112 @@
+use image::DynamicImage;
+
+/// Decode one sRGB channel value (0.0-1.0) to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode one linear light channel value (0.0-1.0) as sRGB.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Whether the image holds linear light.  Float images always do; 8 and 16-bit images are
+/// sRGB encoded.
+pub fn is_linear(img: &DynamicImage) -> bool {
+    matches!(
+        img,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    )
+}
+
+/// Decode the image to linear light floats, so that averaging and mixing pixels behaves like
+/// mixing light.  Alpha is left alone.
+pub fn to_linear(img: DynamicImage) -> DynamicImage {
+    if is_linear(&img) {
+        return img;
+    }
+    let had_alpha = img.color().has_alpha();
+    let mut rgba = img.into_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = srgb_to_linear(pixel[c]);
+        }
+    }
+
+    let linear = DynamicImage::ImageRgba32F(rgba);
+    if had_alpha {
+        linear
+    } else {
+        DynamicImage::ImageRgb32F(linear.into_rgb32f())
+    }
+}
+
+/// Encode a linear light image as 8-bit (or `sixteen_bit`) sRGB.  Images that are already
+/// sRGB encoded are returned unchanged.
+pub fn to_srgb(img: DynamicImage, sixteen_bit: bool) -> DynamicImage {
+    if !is_linear(&img) {
+        return img;
+    }
+    let had_alpha = img.color().has_alpha();
+    let mut rgba = img.into_rgba32f();
+    for pixel in rgba.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = linear_to_srgb(pixel[c]);
+        }
+    }
+
+    let srgb = DynamicImage::ImageRgba32F(rgba);
+    match (sixteen_bit, had_alpha) {
+        (false, false) => DynamicImage::ImageRgb8(srgb.into_rgb8()),
+        (false, true) => DynamicImage::ImageRgba8(srgb.into_rgba8()),
+        (true, false) => DynamicImage::ImageRgb16(srgb.into_rgb16()),
+        (true, true) => DynamicImage::ImageRgba16(srgb.into_rgba16()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb, RgbImage};
+
+    #[test]
+    fn channel_round_trip() {
+        for i in 0..=100 {
+            let value = i as f32 / 100.0;
+            let round_trip = linear_to_srgb(srgb_to_linear(value));
+            assert!((round_trip - value).abs() < 1e-5, "{value}: {round_trip}");
+        }
+        // middle grey in sRGB is about a fifth of the light of white
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+        assert_eq!(linear_to_srgb(1.5), linear_to_srgb(1.0));
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let eight = DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| {
+            Rgb([x as u8 * 16, y as u8 * 16, (x * y) as u8])
+        }));
+        let linear = to_linear(eight.clone());
+        assert!(is_linear(&linear));
+        assert_eq!(to_srgb(linear, false), eight);
+
+        let sixteen = DynamicImage::ImageRgb16(ImageBuffer::from_fn(16, 16, |x, y| {
+            Rgb([x as u16 * 4099, y as u16 * 4001, 7])
+        }));
+        let round_trip = to_srgb(to_linear(sixteen.clone()), true).into_rgb16();
+        for (before, after) in sixteen.into_rgb16().pixels().zip(round_trip.pixels()) {
+            for c in 0..3 {
+                assert!(before[c].abs_diff(after[c]) <= 1, "{before:?} vs {after:?}");
+            }
+        }
+    }
+}
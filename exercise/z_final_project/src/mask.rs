@@ -0,0 +1,145 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma, Rgba, Rgba32FImage};
+
+use crate::blur::fast_gaussian;
+use crate::depth::Depth;
+
+/// A rectangle of the image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Parse a region given as `x,y,width,height`.
+pub fn parse_region(s: &str) -> Result<Region, String> {
+    let numbers = s
+        .split(',')
+        .map(|n| n.trim().parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|e| format!("'{s}': {e}"))?;
+    match numbers[..] {
+        [x, y, width, height] => Ok(Region {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(format!("'{s}' should look like x,y,width,height")),
+    }
+}
+
+/// Build the mask for `--region` or `--mask`, or `None` if neither was given.
+///
+/// White (255) parts of the mask get the full effect of the operation and black (0) parts are
+/// left alone.  Mask images are stretched to the image size and combine their brightness with
+/// their alpha channel.  `feather` softens the mask edge over about that many pixels.
+///
+/// A region that doesn't overlap the image is an error, since the operation would do nothing.
+pub fn build_mask(
+    region: Option<Region>,
+    mask_file: Option<&str>,
+    feather: f32,
+    (width, height): (u32, u32),
+) -> Result<Option<GrayImage>, String> {
+    let mask = match (region, mask_file) {
+        (Some(region), _) => {
+            if region.width == 0 || region.height == 0 || region.x >= width || region.y >= height {
+                return Err(format!(
+                    "--region: {}x{} at {},{} is empty or outside the {width}x{height} image",
+                    region.width, region.height, region.x, region.y
+                ));
+            }
+            region_mask(region, width, height)
+        }
+        (None, Some(path)) => {
+            let img = image::open(path).map_err(|e| format!("{path}: {e}"))?;
+            let img = if img.width() == width && img.height() == height {
+                img
+            } else {
+                img.resize_exact(width, height, FilterType::Triangle)
+            };
+            let img = img.into_luma_alpha8();
+            GrayImage::from_fn(width, height, |x, y| {
+                let [l, a] = img.get_pixel(x, y).0;
+                Luma([(l as u32 * a as u32 / 255) as u8])
+            })
+        }
+        (None, None) => return Ok(None),
+    };
+
+    if feather > 0.0 {
+        let blurred = fast_gaussian(DynamicImage::ImageLuma8(mask), feather / 2.0);
+        Ok(Some(blurred.into_luma8()))
+    } else {
+        Ok(Some(mask))
+    }
+}
+
+fn region_mask(region: Region, width: u32, height: u32) -> GrayImage {
+    let right = region.x.saturating_add(region.width);
+    let bottom = region.y.saturating_add(region.height);
+    GrayImage::from_fn(width, height, |x, y| {
+        let inside = x >= region.x && x < right && y >= region.y && y < bottom;
+        Luma([if inside { 255 } else { 0 }])
+    })
+}
+
+/// Mix `processed` back into `original`, using `mask` to decide how much of each to use.  The
+/// result has the bit depth of `original`.
+pub fn blend_masked(
+    original: &DynamicImage,
+    processed: &DynamicImage,
+    mask: &GrayImage,
+) -> DynamicImage {
+    let depth = Depth::of(original);
+    let has_alpha = original.color().has_alpha() || processed.color().has_alpha();
+    let before = depth.working_copy(original.clone());
+    let after = depth.working_copy(processed.clone());
+
+    let blended = Rgba32FImage::from_fn(before.width(), before.height(), |x, y| {
+        let weight = mask.get_pixel(x, y)[0] as f32 / 255.0;
+        let (b, a) = (before.get_pixel(x, y), after.get_pixel(x, y));
+        Rgba([0, 1, 2, 3].map(|c| b[c] * (1.0 - weight) + a[c] * weight))
+    });
+
+    depth.restore(blended, has_alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions() {
+        let parsed = parse_region("10, 20,30,40").unwrap();
+        assert_eq!(
+            (parsed.x, parsed.y, parsed.width, parsed.height),
+            (10, 20, 30, 40)
+        );
+        for bad in ["", "1,2,3", "1,2,3,4,5", "1,2,3,-4", "a,b,c,d"] {
+            assert!(parse_region(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn regions_must_overlap_the_image() {
+        let region = |x, y, width, height| Region {
+            x,
+            y,
+            width,
+            height,
+        };
+        let mask = build_mask(Some(region(8, 2, 10, 1)), None, 0.0, (10, 4))
+            .unwrap()
+            .unwrap();
+        assert_eq!(mask.get_pixel(9, 2)[0], 255);
+        assert_eq!(mask.get_pixel(7, 2)[0], 0);
+
+        for outside in [region(10, 0, 5, 5), region(0, 4, 5, 5), region(1, 1, 0, 2)] {
+            assert!(build_mask(Some(outside), None, 0.0, (10, 4)).is_err());
+        }
+    }
+}
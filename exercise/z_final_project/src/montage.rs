@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+
+use ab_glyph::FontArc;
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+
+use crate::depth::Depth;
+use crate::overlay::{composite, BlendMode};
+use crate::text::{render_text, TextStyle};
+
+/// Font size used for the file name labels.
+const LABEL_SIZE: f32 = 14.0;
+
+/// How a contact sheet should be laid out.
+pub struct MontageLayout {
+    /// number of tiles per row
+    pub columns: u32,
+    /// size of each tile; images are shrunk (keeping their shape) to fit
+    pub tile: (u32, u32),
+    /// space between tiles and around the edge of the sheet, in pixels
+    pub gap: u32,
+    /// sheet background colour
+    pub background: Rgba<u8>,
+}
+
+/// Parse a `WIDTHxHEIGHT` size such as `200x150`.
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("'{s}' should look like WIDTHxHEIGHT, e.g. 200x200"))?;
+    let width: u32 = width
+        .trim()
+        .parse()
+        .map_err(|e| format!("bad width: {e}"))?;
+    let height: u32 = height
+        .trim()
+        .parse()
+        .map_err(|e| format!("bad height: {e}"))?;
+    if width == 0 || height == 0 {
+        return Err(format!("'{s}' must not have a zero width or height"));
+    }
+    Ok((width, height))
+}
+
+/// Expand glob patterns such as `shots/*.png` into a sorted list of files.  Patterns that don't
+/// contain any wildcards are passed through as they are.
+pub fn expand_patterns(patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let mut matches: Vec<PathBuf> = glob::glob(pattern)
+            .map_err(|e| format!("{pattern}: {e}"))?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect();
+        if matches.is_empty() && !pattern.contains(['*', '?', '[']) {
+            matches.push(PathBuf::from(pattern));
+        }
+        matches.sort();
+        files.extend(matches);
+    }
+    Ok(files)
+}
+
+/// Arrange images in a grid to make a **montage** (contact sheet).
+///
+/// If `label_font` is given, each tile gets its file name written underneath it.
+pub fn montage(
+    images: &[(PathBuf, DynamicImage)],
+    layout: &MontageLayout,
+    label_font: Option<&FontArc>,
+) -> DynamicImage {
+    let columns = layout.columns.clamp(1, images.len().max(1) as u32);
+    let rows = (images.len() as u32).div_ceil(columns).max(1);
+    let (tile_w, tile_h) = layout.tile;
+    let gap = layout.gap;
+
+    let label_style = TextStyle {
+        size: LABEL_SIZE,
+        colour: contrasting(layout.background),
+        outline_width: 0.0,
+        outline_colour: Rgba([0, 0, 0, 0]),
+        shadow_offset: (0, 0),
+        shadow_colour: Rgba([0, 0, 0, 0]),
+    };
+    let label_h = label_font.map_or(0, |font| render_text("Ag", font, &label_style).height());
+
+    let cell_h = tile_h + label_h;
+    let mut sheet = Rgba32FImage::from_pixel(
+        columns * tile_w + (columns + 1) * gap,
+        rows * cell_h + (rows + 1) * gap,
+        Rgba(layout.background.0.map(|c| c as f32 / 255.0)),
+    );
+
+    for (i, (path, img)) in images.iter().enumerate() {
+        let column = i as u32 % columns;
+        let row = i as u32 / columns;
+        let cell_x = (gap + column * (tile_w + gap)) as i64;
+        let cell_y = (gap + row * (cell_h + gap)) as i64;
+
+        let thumb = Depth::Eight.working_copy(img.resize(tile_w, tile_h, FilterType::Triangle));
+        let x = cell_x + (tile_w - thumb.width()) as i64 / 2;
+        let y = cell_y + (tile_h - thumb.height()) as i64 / 2;
+        composite(&mut sheet, &thumb, x, y, 1.0, BlendMode::Normal);
+
+        if let Some(font) = label_font {
+            let label = fitted_label(&file_name(path), font, &label_style, tile_w);
+            let label = DynamicImage::ImageRgba8(label).into_rgba32f();
+            let x = cell_x + (tile_w as i64 - label.width() as i64) / 2;
+            composite(
+                &mut sheet,
+                &label,
+                x,
+                cell_y + tile_h as i64,
+                1.0,
+                BlendMode::Normal,
+            );
+        }
+    }
+
+    Depth::Eight.restore(sheet, layout.background[3] != 255)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Render a label, shortening it with an ellipsis until it fits in `max_width`.
+fn fitted_label(name: &str, font: &FontArc, style: &TextStyle, max_width: u32) -> RgbaImage {
+    let mut label = render_text(name, font, style);
+    let mut chars: Vec<char> = name.chars().collect();
+    while label.width() > max_width && chars.len() > 1 {
+        chars.pop();
+        let shortened: String = chars.iter().collect();
+        label = render_text(&format!("{shortened}…"), font, style);
+    }
+    label
+}
+
+/// Black or white, whichever is easier to read on top of `background`.
+fn contrasting(background: Rgba<u8>) -> Rgba<u8> {
+    let luma =
+        0.299 * background[0] as f32 + 0.587 * background[1] as f32 + 0.114 * background[2] as f32;
+    if luma > 128.0 || background[3] < 128 {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("200x100"), Ok((200, 100)));
+        assert_eq!(parse_size("64X48"), Ok((64, 48)));
+        assert_eq!(parse_size(" 3 x 4 "), Ok((3, 4)));
+        for bad in ["200", "0x10", "10x0", "x10", "ax10", "10x-1", "10x10x10"] {
+            assert!(parse_size(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat, RgbaImage};
+
+use crate::depth::Depth;
+use crate::linear;
+
+/// Save the image, choosing the format from the file extension.
+///
+/// Images keep their bit depth where the format allows it, and are only quantised when it
+/// doesn't: linear light (float) images are encoded as 16 or 8-bit sRGB unless the format
+/// stores floats (OpenEXR), and 16-bit images become 8-bit for formats without 16-bit support.
+///
+/// If `indexed`, because the image was reduced to a palette (e.g. by `quantize` or `dither`),
+/// 8-bit PNGs that use 256 colours or fewer are written as indexed PNGs, which are much smaller
+/// and hold exactly the same pixels.  Palettes of 16, 4 or 2 colours use 4, 2 or 1 bits per
+/// pixel.  Other images keep their colour type, so grayscale stays grayscale.
+pub fn save_image(img: &DynamicImage, path: &str, indexed: bool) -> Result<(), String> {
+    let format = ImageFormat::from_path(path).ok();
+    let converted = convert_for(img, format);
+    let img = converted.as_ref().unwrap_or(img);
+
+    if indexed && format == Some(ImageFormat::Png) && Depth::of(img) == Depth::Eight {
+        let rgba = img.to_rgba8();
+        if let Some((palette, indices)) = index_colours(&rgba) {
+            let file = File::create(Path::new(path)).map_err(|e| e.to_string())?;
+            return write_indexed_png(BufWriter::new(file), rgba.dimensions(), &palette, &indices);
+        }
+    }
+
+    img.save(path).map_err(|e| e.to_string())
+}
+
+/// Encode the image in memory, converting it in the same way as `save_image`.
+pub fn encode_image(
+    img: &DynamicImage,
+    format: ImageFormat,
+    indexed: bool,
+) -> Result<Vec<u8>, String> {
+    let converted = convert_for(img, Some(format));
+    let img = converted.as_ref().unwrap_or(img);
+    let mut bytes = Cursor::new(Vec::new());
+
+    if indexed && format == ImageFormat::Png && Depth::of(img) == Depth::Eight {
+        let rgba = img.to_rgba8();
+        if let Some((palette, indices)) = index_colours(&rgba) {
+            write_indexed_png(&mut bytes, rgba.dimensions(), &palette, &indices)?;
+            return Ok(bytes.into_inner());
+        }
+    }
+
+    img.write_to(&mut bytes, format)
+        .map_err(|e| e.to_string())?;
+    Ok(bytes.into_inner())
+}
+
+/// The image converted to a depth `format` can store, or `None` if it can store it as it is.
+fn convert_for(img: &DynamicImage, format: Option<ImageFormat>) -> Option<DynamicImage> {
+    let sixteen_bit = matches!(
+        format,
+        Some(ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm)
+    );
+    let float = format == Some(ImageFormat::OpenExr);
+
+    match Depth::of(img) {
+        Depth::Float if !float => Some(linear::to_srgb(img.clone(), sixteen_bit)),
+        Depth::Eight | Depth::Sixteen if float => Some(linear::to_linear(img.clone())),
+        Depth::Sixteen if !sixteen_bit => Some(to_eight_bit(img)),
+        _ => None,
+    }
+}
+
+/// Convert an image to 8 bits per channel, keeping its colour channels.
+fn to_eight_bit(img: &DynamicImage) -> DynamicImage {
+    match (img.color().has_color(), img.color().has_alpha()) {
+        (false, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (false, true) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (true, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (true, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+/// Whether the format chosen by the file extension can store float (linear light) images.
+pub fn stores_float(path: &str) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| format == ImageFormat::OpenExr)
+}
+
+/// Build a palette for the image, or `None` if it has more than 256 colours.
+fn index_colours(img: &RgbaImage) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut palette = Vec::new();
+    let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut indices = Vec::with_capacity((img.width() * img.height()) as usize);
+
+    for pixel in img.pixels() {
+        let index = match lookup.get(&pixel.0) {
+            Some(&index) => index,
+            None => {
+                if palette.len() == 256 {
+                    return None;
+                }
+                let index = palette.len() as u8;
+                palette.push(pixel.0);
+                lookup.insert(pixel.0, index);
+                index
+            }
+        };
+        indices.push(index);
+    }
+
+    Some((palette, indices))
+}
+
+fn write_indexed_png(
+    writer: impl Write,
+    (width, height): (u32, u32),
+    palette: &[[u8; 4]],
+    indices: &[u8],
+) -> Result<(), String> {
+    let (depth, bits) = match palette.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect::<Vec<u8>>(),
+    );
+    if palette.iter().any(|c| c[3] != 255) {
+        encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<u8>>());
+    }
+
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer
+        .write_image_data(&pack_rows(indices, width as usize, bits))
+        .map_err(|e| e.to_string())
+}
+
+/// Pack palette indices into rows of `bits` bits per pixel, most significant bits first, with
+/// every row starting on a new byte as PNG requires.
+fn pack_rows(indices: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    if bits == 8 {
+        return indices.to_vec();
+    }
+
+    let per_byte = 8 / bits;
+    let mut packed = Vec::with_capacity(indices.len() / per_byte + 1);
+    for row in indices.chunks(width.max(1)) {
+        for pixels in row.chunks(per_byte) {
+            let mut byte = 0;
+            for (i, &index) in pixels.iter().enumerate() {
+                byte |= index << (8 - bits * (i + 1));
+            }
+            packed.push(byte);
+        }
+    }
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::{self, PaletteMethod};
+
+    /// The colour type and bit depth of an encoded PNG.
+    fn png_header(bytes: &[u8]) -> (png::ColorType, png::BitDepth) {
+        let reader = png::Decoder::new(bytes).read_info().unwrap();
+        let info = reader.info();
+        (info.color_type, info.bit_depth)
+    }
+
+    #[test]
+    fn quantized_images_with_alpha_are_indexed() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            image::Rgba([x as u8 * 4, y as u8 * 4, 100, (x + y) as u8 * 2])
+        }));
+        for (colours, depth) in [
+            (2, png::BitDepth::One),
+            (4, png::BitDepth::Two),
+            (16, png::BitDepth::Four),
+            (256, png::BitDepth::Eight),
+        ] {
+            let quantized = palette::quantize(img.clone(), colours, PaletteMethod::Kmeans);
+            let bytes = encode_image(&quantized, ImageFormat::Png, true).unwrap();
+            assert_eq!(
+                png_header(&bytes),
+                (png::ColorType::Indexed, depth),
+                "{colours} colours"
+            );
+            let decoded = image::load_from_memory(&bytes).unwrap();
+            assert_eq!(decoded.to_rgba8(), quantized.to_rgba8());
+        }
+    }
+
+    #[test]
+    fn images_with_many_colours_are_not_indexed() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([x as u8 * 8, y as u8 * 8, 0])
+        }));
+        let bytes = encode_image(&img, ImageFormat::Png, true).unwrap();
+        assert_eq!(
+            png_header(&bytes),
+            (png::ColorType::Rgb, png::BitDepth::Eight)
+        );
+    }
+
+    #[test]
+    fn only_palette_images_are_indexed() {
+        let gray = DynamicImage::ImageLuma8(image::GrayImage::from_fn(16, 16, |x, _| {
+            image::Luma([x as u8 * 16])
+        }));
+        let bytes = encode_image(&gray, ImageFormat::Png, false).unwrap();
+        assert_eq!(
+            png_header(&bytes),
+            (png::ColorType::Grayscale, png::BitDepth::Eight)
+        );
+        assert_eq!(image::load_from_memory(&bytes).unwrap(), gray);
+    }
+
+    #[test]
+    fn rows_start_on_a_new_byte() {
+        assert_eq!(
+            pack_rows(&[1, 0, 1, 1, 0, 1], 3, 1),
+            [0b1010_0000, 0b1010_0000]
+        );
+        assert_eq!(pack_rows(&[3, 2, 1], 3, 2), [0b1110_0100]);
+        assert_eq!(pack_rows(&[15, 1, 2], 3, 4), [0xf1, 0x20]);
+    }
+}
@@ -0,0 +1,198 @@
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::depth::Depth;
+use crate::gravity::Gravity;
+
+/// How the colours of an overlay are mixed with the image underneath it.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// overlay colours replace the image colours
+    Normal,
+    /// darken by multiplying the colours together
+    Multiply,
+    /// lighten by multiplying the inverted colours together
+    Screen,
+    /// multiply dark areas and screen light areas of the image
+    Overlay,
+    /// a gentler version of overlay
+    SoftLight,
+}
+
+impl BlendMode {
+    /// Blend a single backdrop channel `b` with a source channel `s`.  Both are in 0.0-1.0.
+    ///
+    /// The formulas follow the W3C Compositing and Blending spec.
+    pub fn blend(self, b: f32, s: f32) -> f32 {
+        match self {
+            BlendMode::Normal => s,
+            BlendMode::Multiply => b * s,
+            BlendMode::Screen => b + s - b * s,
+            BlendMode::Overlay => {
+                if b <= 0.5 {
+                    2.0 * b * s
+                } else {
+                    1.0 - 2.0 * (1.0 - b) * (1.0 - s)
+                }
+            }
+            BlendMode::SoftLight => {
+                if s <= 0.5 {
+                    b - (1.0 - 2.0 * s) * b * (1.0 - b)
+                } else {
+                    let d = if b <= 0.25 {
+                        ((16.0 * b - 12.0) * b + 4.0) * b
+                    } else {
+                        b.sqrt()
+                    };
+                    b + (2.0 * s - 1.0) * (d - b)
+                }
+            }
+        }
+    }
+}
+
+/// **Overlay** another image (e.g. a watermark logo) on top of the image.
+///
+/// The overlay is scaled by `scale`, placed according to `gravity` and `offset`, and mixed in
+/// using `blend` with its own alpha channel multiplied by `opacity`.
+pub fn overlay(
+    img: DynamicImage,
+    top: &DynamicImage,
+    gravity: Gravity,
+    offset: (i64, i64),
+    scale: f32,
+    opacity: f32,
+    blend: BlendMode,
+) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let mut base = depth.working_copy(img);
+
+    let top = if scale == 1.0 {
+        depth.working_copy(top.clone())
+    } else {
+        let width = ((top.width() as f32 * scale).round() as u32).max(1);
+        let height = ((top.height() as f32 * scale).round() as u32).max(1);
+        depth.working_copy(top.resize_exact(width, height, FilterType::Lanczos3))
+    };
+
+    let (x, y) = gravity.position(base.dimensions(), top.dimensions(), offset);
+    composite(&mut base, &top, x, y, opacity.clamp(0.0, 1.0), blend);
+
+    depth.restore(base, had_alpha)
+}
+
+/// Composite `top` onto `base` with its top left corner at (`x`, `y`).  Any part of `top` that
+/// falls outside of `base` is ignored.  Both images should use the same encoding (see
+/// `Depth::working_copy`).
+pub fn composite(
+    base: &mut Rgba32FImage,
+    top: &Rgba32FImage,
+    x: i64,
+    y: i64,
+    opacity: f32,
+    blend: BlendMode,
+) {
+    let (base_w, base_h) = base.dimensions();
+
+    for (tx, ty, top_pixel) in top.enumerate_pixels() {
+        let bx = x + tx as i64;
+        let by = y + ty as i64;
+        if bx < 0 || by < 0 || bx >= base_w as i64 || by >= base_h as i64 {
+            continue;
+        }
+
+        let base_pixel = base.get_pixel_mut(bx as u32, by as u32);
+        *base_pixel = blend_pixel(*base_pixel, *top_pixel, opacity, blend);
+    }
+}
+
+/// Source-over composite a single pixel, using `blend` to mix the colours where they overlap.
+fn blend_pixel(base: Rgba<f32>, top: Rgba<f32>, opacity: f32, blend: BlendMode) -> Rgba<f32> {
+    let alpha_s = top[3].clamp(0.0, 1.0) * opacity;
+    let alpha_b = base[3].clamp(0.0, 1.0);
+    let alpha_o = alpha_s + alpha_b * (1.0 - alpha_s);
+    if alpha_o <= 0.0 {
+        return Rgba([0.0; 4]);
+    }
+
+    let mut out = [0.0; 4];
+    for c in 0..3 {
+        let (cb, cs) = (base[c], top[c]);
+        let mixed = (1.0 - alpha_b) * cs + alpha_b * blend.blend(cb, cs);
+        let co = (alpha_s * mixed + alpha_b * cb * (1.0 - alpha_s)) / alpha_o;
+        out[c] = co.clamp(0.0, 1.0);
+    }
+    out[3] = alpha_o;
+
+    Rgba(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn blend_modes() {
+        let cases = [
+            (BlendMode::Normal, 0.25, 0.5, 0.5),
+            (BlendMode::Multiply, 0.25, 0.5, 0.125),
+            (BlendMode::Screen, 0.25, 0.5, 0.625),
+            (BlendMode::Overlay, 0.25, 0.5, 0.25),
+            (BlendMode::Overlay, 0.75, 0.5, 0.75),
+            (BlendMode::Overlay, 0.75, 0.0, 0.5),
+            (BlendMode::SoftLight, 0.25, 0.5, 0.25),
+            (BlendMode::SoftLight, 0.25, 0.75, 0.375),
+            (BlendMode::SoftLight, 0.64, 1.0, 0.8),
+        ];
+        for (mode, b, s, expected) in cases {
+            let blended = mode.blend(b, s);
+            assert!(
+                (blended - expected).abs() < 1e-6,
+                "{mode:?} {b} {s}: {blended}"
+            );
+        }
+    }
+
+    #[test]
+    fn overlays_are_placed_and_mixed() {
+        let base = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([255, 255, 255])));
+        let red = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([255, 0, 0])));
+        let result = overlay(
+            base,
+            &red,
+            Gravity::SouthEast,
+            (0, 0),
+            1.0,
+            1.0,
+            BlendMode::Multiply,
+        )
+        .into_rgb8();
+        for (x, y, pixel) in result.enumerate_pixels() {
+            let expected = if x >= 2 && y >= 2 {
+                [255, 0, 0]
+            } else {
+                [255; 3]
+            };
+            assert_eq!(pixel.0, expected, "{x},{y}");
+        }
+    }
+
+    #[test]
+    fn opacity_fades_the_overlay() {
+        let black = DynamicImage::ImageRgb8(RgbImage::new(2, 2));
+        let white = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([255, 255, 255])));
+        let result = overlay(
+            black,
+            &white,
+            Gravity::Center,
+            (0, 0),
+            1.0,
+            0.5,
+            BlendMode::Normal,
+        );
+        assert_eq!(result.into_rgb8().get_pixel(1, 1), &Rgb([128, 128, 128]));
+    }
+}
@@ -0,0 +1,230 @@
+use clap::ValueEnum;
+use image::{DynamicImage, Rgb, RgbImage, Rgba};
+
+/// Most pixels looked at when working out a palette; bigger images are sampled.
+const MAX_SAMPLES: usize = 100_000;
+
+/// Number of refinement passes made by k-means.
+const KMEANS_ITERATIONS: usize = 10;
+
+/// How to choose the colours of a palette.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaletteMethod {
+    /// repeatedly split the colour space at the median of its widest channel
+    MedianCut,
+    /// start from median cut and refine with k-means clustering
+    Kmeans,
+}
+
+/// One colour of a palette and how much of the image it covers.
+pub struct Swatch {
+    pub colour: Rgb<u8>,
+    /// fraction of the image closest to this colour (0.0-1.0)
+    pub fraction: f32,
+}
+
+/// Find the `colours` dominant colours of the image, most common first.
+pub fn extract_palette(img: &DynamicImage, colours: usize, method: PaletteMethod) -> Vec<Swatch> {
+    let samples = sample_pixels(img.to_rgb8().pixels().map(|p| p.0));
+    if samples.is_empty() {
+        return Vec::new();
+    }
+
+    let palette = build_palette(&samples, colours, method);
+
+    let mut counts = vec![0usize; palette.len()];
+    for sample in &samples {
+        counts[nearest(&palette, *sample)] += 1;
+    }
+
+    let mut swatches: Vec<Swatch> = palette
+        .iter()
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(colour, count)| Swatch {
+            colour: to_rgb(*colour),
+            fraction: count as f32 / samples.len() as f32,
+        })
+        .collect();
+    swatches.sort_by(|a, b| b.fraction.total_cmp(&a.fraction));
+    swatches
+}
+
+/// **Quantize** the image down to at most `colours` colours.
+///
+/// Alpha counts as part of the colour, so an image with alpha ends up with at most `colours`
+/// different RGBA values, which is what an indexed PNG's palette holds.
+pub fn quantize(img: DynamicImage, colours: usize, method: PaletteMethod) -> DynamicImage {
+    if img.color().has_alpha() {
+        let mut rgba = img.into_rgba8();
+        let samples = sample_pixels(rgba.pixels().map(|p| p.0));
+        if samples.is_empty() {
+            return DynamicImage::ImageRgba8(rgba);
+        }
+        let palette = build_palette(&samples, colours, method);
+        for pixel in rgba.pixels_mut() {
+            let colour = palette[nearest(&palette, pixel.0.map(f32::from))];
+            *pixel = Rgba(colour.map(|c| c.round().clamp(0.0, 255.0) as u8));
+        }
+        return DynamicImage::ImageRgba8(rgba);
+    }
+
+    let palette: Vec<Rgb<u8>> = extract_palette(&img, colours, method)
+        .iter()
+        .map(|swatch| swatch.colour)
+        .collect();
+    remap(img, &palette)
+}
+
+/// Replace every pixel with the closest colour from `palette`.  Any alpha channel is kept as is.
+pub fn remap(img: DynamicImage, palette: &[Rgb<u8>]) -> DynamicImage {
+    let palette: Vec<[f32; 3]> = palette.iter().map(|c| to_f32(*c)).collect();
+    if palette.is_empty() {
+        return img;
+    }
+
+    if img.color().has_alpha() {
+        let mut rgba = img.into_rgba8();
+        for pixel in rgba.pixels_mut() {
+            let Rgba([r, g, b, a]) = *pixel;
+            let Rgb([r, g, b]) = to_rgb(palette[nearest(&palette, to_f32(Rgb([r, g, b])))]);
+            *pixel = Rgba([r, g, b, a]);
+        }
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgb = img.into_rgb8();
+        for pixel in rgb.pixels_mut() {
+            *pixel = to_rgb(palette[nearest(&palette, to_f32(*pixel))]);
+        }
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
+
+/// Draw the palette as a strip of equally sized squares, most common colour on the left.
+pub fn swatch_strip(swatches: &[Swatch], size: u32) -> DynamicImage {
+    let width = size * swatches.len().max(1) as u32;
+    let strip = RgbImage::from_fn(width, size, |x, _| {
+        swatches
+            .get((x / size) as usize)
+            .map_or(Rgb([0, 0, 0]), |swatch| swatch.colour)
+    });
+    DynamicImage::ImageRgb8(strip)
+}
+
+/// Format a colour as `#rrggbb`.
+pub fn hex(colour: Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour[0], colour[1], colour[2])
+}
+
+/// Index of the palette colour closest to `colour` (by squared distance over the channels).
+pub fn nearest<const N: usize>(palette: &[[f32; N]], colour: [f32; N]) -> usize {
+    let distance = |p: &[f32; N]| (0..N).map(|c| (p[c] - colour[c]).powi(2)).sum::<f32>();
+    palette
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+        .map_or(0, |(i, _)| i)
+}
+
+pub fn to_f32(colour: Rgb<u8>) -> [f32; 3] {
+    [colour[0] as f32, colour[1] as f32, colour[2] as f32]
+}
+
+pub fn to_rgb(colour: [f32; 3]) -> Rgb<u8> {
+    Rgb(colour.map(|c| c.round().clamp(0.0, 255.0) as u8))
+}
+
+/// Take an evenly spread sample of at most `MAX_SAMPLES` pixels.
+fn sample_pixels<const N: usize>(pixels: impl ExactSizeIterator<Item = [u8; N]>) -> Vec<[f32; N]> {
+    let step = pixels.len().div_ceil(MAX_SAMPLES).max(1);
+    pixels.step_by(step).map(|p| p.map(f32::from)).collect()
+}
+
+/// Choose a palette of at most `colours` colours for the samples.
+fn build_palette<const N: usize>(
+    samples: &[[f32; N]],
+    colours: usize,
+    method: PaletteMethod,
+) -> Vec<[f32; N]> {
+    let palette = median_cut(samples, colours.max(1));
+    match method {
+        PaletteMethod::MedianCut => palette,
+        PaletteMethod::Kmeans => kmeans(samples, palette),
+    }
+}
+
+/// Median cut: split the box with the widest channel range at its median until there are
+/// `colours` boxes, then use the average colour of each box.
+fn median_cut<const N: usize>(samples: &[[f32; N]], colours: usize) -> Vec<[f32; N]> {
+    let mut boxes: Vec<Vec<[f32; N]>> = vec![samples.to_vec()];
+
+    while boxes.len() < colours {
+        // find the box and channel with the biggest spread
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .flat_map(|(i, b)| (0..N).map(move |c| (i, c, channel_range(b, c))))
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+        let Some((index, channel, range)) = widest else {
+            break;
+        };
+        if range <= 0.0 {
+            break;
+        }
+
+        let mut pixels = boxes.swap_remove(index);
+        pixels.sort_by(|a, b| a[channel].total_cmp(&b[channel]));
+        let upper = pixels.split_off(pixels.len() / 2);
+        boxes.push(pixels);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|b| average(b)).collect()
+}
+
+/// Refine a palette with Lloyd's k-means algorithm.
+fn kmeans<const N: usize>(samples: &[[f32; N]], mut centres: Vec<[f32; N]>) -> Vec<[f32; N]> {
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![[0.0f64; N]; centres.len()];
+        let mut counts = vec![0usize; centres.len()];
+        for sample in samples {
+            let i = nearest(&centres, *sample);
+            for c in 0..N {
+                sums[i][c] += sample[c] as f64;
+            }
+            counts[i] += 1;
+        }
+
+        let mut moved = false;
+        for (i, centre) in centres.iter_mut().enumerate() {
+            if counts[i] == 0 {
+                continue;
+            }
+            let mean = sums[i].map(|sum| (sum / counts[i] as f64) as f32);
+            moved |= (0..N).any(|c| (mean[c] - centre[c]).abs() > 0.5);
+            *centre = mean;
+        }
+        if !moved {
+            break;
+        }
+    }
+    centres
+}
+
+fn channel_range<const N: usize>(pixels: &[[f32; N]], channel: usize) -> f32 {
+    let (min, max) = pixels.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+        (min.min(p[channel]), max.max(p[channel]))
+    });
+    max - min
+}
+
+fn average<const N: usize>(pixels: &[[f32; N]]) -> [f32; N] {
+    let mut sum = [0.0f64; N];
+    for p in pixels {
+        for c in 0..N {
+            sum[c] += p[c] as f64;
+        }
+    }
+    sum.map(|s| (s / pixels.len().max(1) as f64) as f32)
+}
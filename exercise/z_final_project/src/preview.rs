@@ -0,0 +1,67 @@
+use std::fmt::Write;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb};
+
+use crate::linear;
+
+/// Characters used for ASCII art, from darkest to brightest.
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Preview width used when neither `--width` nor `$COLUMNS` is set.
+const DEFAULT_WIDTH: u32 = 80;
+
+/// Width to draw previews at: the requested width, or the terminal width if the shell tells us.
+pub fn preview_width(requested: Option<u32>) -> u32 {
+    requested
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+        .max(1)
+}
+
+/// Render the image for a 24-bit colour terminal, `width` characters wide.
+///
+/// Each character is an upper half block with its foreground set to one pixel and its
+/// background set to the pixel below, so every character shows two square-ish pixels.
+pub fn render_blocks(img: &DynamicImage, width: u32) -> String {
+    let width = width.min(img.width()).max(1);
+    let height = (img.height() as u64 * width as u64 / img.width().max(1) as u64).max(1) as u32;
+    let small =
+        linear::to_srgb(img.resize_exact(width, height, FilterType::Triangle), false).into_rgb8();
+
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let Rgb([r, g, b]) = *small.get_pixel(x, y);
+            let _ = write!(out, "\x1b[38;2;{r};{g};{b}m");
+            if y + 1 < height {
+                let Rgb([r, g, b]) = *small.get_pixel(x, y + 1);
+                let _ = write!(out, "\x1b[48;2;{r};{g};{b}m");
+            } else {
+                out.push_str("\x1b[49m");
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// Render the image as plain ASCII art, `width` characters wide, for terminals without colour.
+pub fn render_ascii(img: &DynamicImage, width: u32) -> String {
+    let width = width.max(1);
+    // characters are about twice as tall as they are wide
+    let height = (img.height() as u64 * width as u64 / img.width().max(1) as u64 / 2).max(1) as u32;
+    let small =
+        linear::to_srgb(img.resize_exact(width, height, FilterType::Triangle), false).into_luma8();
+
+    let mut out = String::new();
+    for y in 0..height {
+        for x in 0..width {
+            let level = small.get_pixel(x, y)[0] as usize * (ASCII_RAMP.len() - 1) / 255;
+            out.push(ASCII_RAMP[level] as char);
+        }
+        out.push('\n');
+    }
+    out
+}
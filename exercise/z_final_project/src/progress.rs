@@ -0,0 +1,172 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Characters in the bar itself.
+const BAR_WIDTH: u64 = 30;
+
+/// Draw progress bars from now on (`--progress`).  They are hidden by default.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// A progress bar on stderr, e.g. `fractal [#########---------]  30%  240/800`.
+///
+/// Nothing is drawn unless `enable` has been called.  The bar is completed and ended with a
+/// newline when it is dropped.
+pub struct Progress {
+    label: String,
+    total: u64,
+    done: u64,
+    /// percentage last drawn, so the bar is only redrawn when it changes
+    drawn: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: u64) -> Progress {
+        let mut progress = Progress {
+            label: label.to_string(),
+            total,
+            done: 0,
+            drawn: None,
+        };
+        progress.draw();
+        progress
+    }
+
+    /// Record that `done` of the total are finished.
+    pub fn set(&mut self, done: u64) {
+        self.done = done.min(self.total);
+        self.draw();
+    }
+
+    pub fn inc(&mut self) {
+        self.set(self.done + 1);
+    }
+
+    fn draw(&mut self) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let percent = self.percent();
+        if self.drawn == Some(percent) {
+            return;
+        }
+        self.drawn = Some(percent);
+
+        eprint!("\r{}", self.bar());
+        let _ = std::io::stderr().flush();
+    }
+
+    /// How much is finished, rounded down to a whole percentage.  Nothing to do counts as done.
+    fn percent(&self) -> u64 {
+        (self.done * 100).checked_div(self.total).unwrap_or(100)
+    }
+
+    /// The bar as it is drawn, without the carriage return that goes back over the last one.
+    fn bar(&self) -> String {
+        let filled = (self.done * BAR_WIDTH)
+            .checked_div(self.total)
+            .unwrap_or(BAR_WIDTH);
+        format!(
+            "{} [{}{}] {:>3}%  {}/{}",
+            self.label,
+            "#".repeat(filled as usize),
+            "-".repeat((BAR_WIDTH - filled) as usize),
+            self.percent(),
+            self.done,
+            self.total
+        )
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.drawn.is_some() {
+            self.set(self.total);
+            eprintln!();
+        }
+    }
+}
+
+/// How long each step of a run took, printed by `--timings`.
+#[derive(Default)]
+pub struct Timings {
+    steps: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    /// Run `step`, recording how long it took under `name`.
+    pub fn time<T>(&mut self, name: &str, step: impl FnOnce() -> T) -> T {
+        let start = std::time::Instant::now();
+        let result = step();
+        self.steps.push((name.to_string(), start.elapsed()));
+        result
+    }
+
+    /// Print the steps and their total to stderr.
+    pub fn print(&self) {
+        eprint!("{}", self.table());
+    }
+
+    /// The steps and their total, a line each, with the times lined up.
+    fn table(&self) -> String {
+        let width = self
+            .steps
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let width = width.max("total".len());
+        let total: Duration = self.steps.iter().map(|(_, duration)| *duration).sum();
+        self.steps
+            .iter()
+            .map(|(name, duration)| (name.as_str(), *duration))
+            .chain([("total", total)])
+            .map(|(name, duration)| format!("{name:<width$}  {duration:>10.2?}\n"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bars_fill_with_the_percentage() {
+        let mut progress = Progress::new("blur", 8);
+        progress.set(3);
+        assert_eq!(progress.percent(), 37);
+        assert_eq!(
+            progress.bar(),
+            format!("blur [{}{}]  37%  3/8", "#".repeat(11), "-".repeat(19))
+        );
+
+        progress.set(20);
+        assert_eq!(progress.percent(), 100);
+        assert!(progress.bar().ends_with("] 100%  8/8"));
+        assert!(!progress.bar().contains('-'));
+
+        let nothing = Progress::new("empty", 0);
+        assert_eq!(nothing.percent(), 100);
+        assert!(!nothing.bar().contains('-'));
+    }
+
+    #[test]
+    fn timings_are_lined_up_with_a_total() {
+        let timings = Timings {
+            steps: vec![
+                ("decode".to_string(), Duration::from_millis(12)),
+                ("median filter".to_string(), Duration::from_micros(2500)),
+            ],
+        };
+        assert_eq!(
+            timings.table(),
+            "decode            12.00ms\n\
+             median filter      2.50ms\n\
+             total             14.50ms\n"
+        );
+    }
+}
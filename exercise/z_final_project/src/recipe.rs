@@ -0,0 +1,43 @@
+use serde::Deserialize;
+
+use crate::shell;
+
+/// A list of operations to apply to every image, read from a TOML file:
+///
+/// ```toml
+/// operations = ["blur 1", "text 'Proof' --gravity south-east"]
+/// format = "jpg"
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Recipe {
+    /// operations in the order they are applied
+    pub operations: Vec<OperationSpec>,
+    /// extension (and so format) of the output files, if not the same as the input
+    pub format: Option<String>,
+}
+
+/// An operation written as a line (`"blur 2"`) or as its words (`["blur", "2"]`, as in
+/// session files).
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum OperationSpec {
+    Line(String),
+    Words(Vec<String>),
+}
+
+impl OperationSpec {
+    pub fn into_words(self) -> Result<Vec<String>, String> {
+        match self {
+            OperationSpec::Line(line) => shell::split_words(&line),
+            OperationSpec::Words(words) => Ok(words),
+        }
+    }
+}
+
+impl Recipe {
+    pub fn load(path: &str) -> Result<Recipe, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        toml::from_str(&text).map_err(|e| format!("{path}: {e}"))
+    }
+}
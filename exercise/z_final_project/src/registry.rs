@@ -0,0 +1,241 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use clap::{ArgMatches, Command};
+use image::{ColorType, DynamicImage};
+
+/// An operation on an image, e.g. `blur`.
+///
+/// Every operation mirage knows about is an `ImageOp` in a `Registry`, which is what the command
+/// line, sessions, recipes, the shell and the server all look operations up in.  To add an
+/// operation of your own, implement this trait and register it:
+///
+/// ```no_run
+/// use clap::{Arg, ArgMatches, Command};
+/// use image::DynamicImage;
+/// use mirage::{Action, ImageOp, Registry};
+///
+/// struct Posterize;
+///
+/// struct PosterizeTo {
+///     levels: u8,
+/// }
+///
+/// impl ImageOp for Posterize {
+///     fn name(&self) -> &str {
+///         "posterize"
+///     }
+///
+///     fn command(&self) -> Command {
+///         Command::new("posterize")
+///             .about("Reduce each channel to a few levels")
+///             .arg(Arg::new("levels").value_parser(clap::value_parser!(u8)).required(true))
+///     }
+///
+///     fn parse(&self, matches: &ArgMatches) -> Result<Box<dyn Action>, clap::Error> {
+///         let (_, args) = matches.subcommand().unwrap();
+///         let levels = *args.get_one::<u8>("levels").unwrap();
+///         Ok(Box::new(PosterizeTo { levels }))
+///     }
+/// }
+///
+/// impl Action for PosterizeTo {
+///     fn apply(&self, img: DynamicImage) -> Result<DynamicImage, String> {
+///         let step = 255 / self.levels.max(2) as u32;
+///         let mut rgba = img.into_rgba8();
+///         for pixel in rgba.pixels_mut() {
+///             for c in 0..3 {
+///                 pixel[c] = (pixel[c] as u32 / step * step) as u8;
+///             }
+///         }
+///         Ok(DynamicImage::ImageRgba8(rgba))
+///     }
+/// }
+///
+/// let mut registry = Registry::builtin();
+/// registry.register(Posterize);
+/// mirage::cli::run(registry);
+/// ```
+pub trait ImageOp: Send + Sync {
+    /// The name typed on the command line, e.g. `blur`.
+    fn name(&self) -> &str;
+
+    /// The arguments the operation takes, as a clap command called `name()`.  Its `about` text
+    /// is what `--help` shows for the operation.
+    fn command(&self) -> Command;
+
+    /// Read the operation's arguments into the `Action` that applies it.  `matches` are for a
+    /// command built with `Registry::augment`, so `matches.subcommand()` is this operation and
+    /// its arguments (the same as clap derive's `Subcommand::from_arg_matches` expects).
+    ///
+    /// Arguments are parsed once, here, however many images the action is applied to.
+    fn parse(&self, matches: &ArgMatches) -> Result<Box<dyn Action>, clap::Error>;
+
+    /// The arguments `bench` times the operation with, one run per list, e.g. `[["4"], ["4",
+    /// "--method", "box"]]` for `blur`.  `input` is the image being benchmarked, for operations
+    /// that read a second image.  By default the operation is timed once, with no arguments.
+    fn bench_args(&self, _input: &str) -> Vec<Vec<String>> {
+        vec![Vec::new()]
+    }
+}
+
+/// An operation with its arguments parsed, ready to apply to any number of images.
+pub trait Action: Any + Send + Sync {
+    /// Apply the operation to `img`.  `Step::apply` only calls this once `check` has passed
+    /// for `img`.
+    fn apply(&self, img: DynamicImage) -> Result<DynamicImage, String>;
+
+    /// Check the arguments against an image of the shape `input` without touching any pixels,
+    /// and return the shape of the result.  This is what `--dry-run` and `serve` rely on, and
+    /// what guards `apply`, so it should be cheap and agree with `apply`; by default the shape
+    /// is unchanged.
+    fn check(&self, input: Shape) -> Result<Shape, String> {
+        Ok(input)
+    }
+
+    /// Whether the operation reads other files, e.g. an overlay image.  `serve` refuses these.
+    fn reads_files(&self) -> bool {
+        false
+    }
+
+    /// Whether the operation reduces the image to a palette, e.g. `quantize`, so that PNGs of
+    /// the result are written indexed (see `output::save_image`).
+    fn makes_palette(&self) -> bool {
+        false
+    }
+
+    /// Check the arguments won't make the operation too slow or memory hungry for `serve` or
+    /// `watch`, e.g. a blur of thousands of pixels.  Sizes are limited separately, from `check`, so this
+    /// is only for arguments that cost time for every pixel.  By default everything passes.
+    fn check_cost(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// The size and colour type of an image: everything `Action::check` has to go on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub width: u32,
+    pub height: u32,
+    pub colour: ColorType,
+}
+
+impl Shape {
+    /// The shape of `img`.
+    pub fn of(img: &DynamicImage) -> Shape {
+        Shape {
+            width: img.width(),
+            height: img.height(),
+            colour: img.color(),
+        }
+    }
+
+    /// The number of pixels in the image.
+    pub fn pixels(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+/// The operations available, by name.
+#[derive(Clone, Default)]
+pub struct Registry {
+    ops: Vec<Arc<dyn ImageOp>>,
+}
+
+impl Registry {
+    /// An empty registry; `Registry::builtin` has mirage's own operations.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Add an operation, replacing any already registered under the same name.
+    pub fn register(&mut self, op: impl ImageOp + 'static) {
+        self.ops.retain(|existing| existing.name() != op.name());
+        self.ops.push(Arc::new(op));
+    }
+
+    /// The operation registered as `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn ImageOp>> {
+        self.ops.iter().find(|op| op.name() == name)
+    }
+
+    /// The operations in the order they were registered.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn ImageOp>> {
+        self.ops.iter()
+    }
+
+    /// Add every operation to `command` as a subcommand.  `--help` lists them in the order
+    /// they were registered, after any subcommands `command` already has.
+    pub fn augment(&self, command: Command) -> Command {
+        command.subcommands(self.ops.iter().map(|op| op.command().display_order(None)))
+    }
+
+    /// The operation chosen in `matches` (from a command built with `augment`), with its
+    /// arguments parsed, or `None` if the subcommand isn't an operation.
+    pub fn step(&self, matches: &ArgMatches) -> Option<Result<Step, clap::Error>> {
+        let (name, _) = matches.subcommand()?;
+        let op = self.get(name)?;
+        Some(op.parse(matches).map(|action| Step {
+            name: op.name().to_string(),
+            action: Arc::from(action),
+        }))
+    }
+
+    /// Parse an operation written as words, e.g. `["blur", "2"]`.
+    pub fn parse<S: AsRef<str>>(&self, words: &[S]) -> Result<Step, clap::Error> {
+        let command = Command::new("operation")
+            .no_binary_name(true)
+            .subcommand_required(true);
+        let matches = self
+            .augment(command)
+            .try_get_matches_from(words.iter().map(AsRef::as_ref))?;
+        self.step(&matches)
+            .expect("a subcommand is required, and they are all operations")
+    }
+}
+
+/// An operation together with the arguments to apply it with.
+#[derive(Clone)]
+pub struct Step {
+    name: String,
+    action: Arc<dyn Action>,
+}
+
+impl Step {
+    /// The name of the operation, e.g. `blur`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The parsed operation, e.g. to downcast to a type of your own with `Any`.
+    pub fn action(&self) -> &dyn Action {
+        self.action.as_ref()
+    }
+
+    /// Check the arguments against `img` and apply the operation to it.
+    pub fn apply(&self, img: DynamicImage) -> Result<DynamicImage, String> {
+        self.action.check(Shape::of(&img))?;
+        self.action.apply(img)
+    }
+
+    /// The shape of the result of applying the operation to an image of the shape `input`, or
+    /// why it can't be applied; see `Action::check`.
+    pub fn check(&self, input: Shape) -> Result<Shape, String> {
+        self.action.check(input)
+    }
+
+    /// Whether the operation reads other files; see `Action::reads_files`.
+    pub fn reads_files(&self) -> bool {
+        self.action.reads_files()
+    }
+
+    /// Whether the operation reduces the image to a palette; see `Action::makes_palette`.
+    pub fn makes_palette(&self) -> bool {
+        self.action.makes_palette()
+    }
+
+    /// Whether the arguments are cheap enough for `serve` and `watch`; see `Action::check_cost`.
+    pub fn check_cost(&self) -> Result<(), String> {
+        self.action.check_cost()
+    }
+}
@@ -0,0 +1,120 @@
+use clap::ValueEnum;
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::depth::Depth;
+
+/// Ways of making an image tile seamlessly.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeamlessMethod {
+    /// blend the image with copies shifted by half its width and half its height, which wrap
+    /// at the edges; keeps the size, but ghosts detail where they overlap
+    Blend,
+    /// add mirrored copies to the right and below, doubling the size; always exact, but the
+    /// symmetry shows
+    Mirror,
+}
+
+/// Make the image tile **seamlessly**.
+///
+/// For `Blend`, `overlap` is how far in from each edge (as a fraction of the size, 0.0-1.0) the
+/// image fades into the shifted copy.  Larger overlaps give smoother but more ghosted results.
+pub fn seamless(img: DynamicImage, method: SeamlessMethod, overlap: f32) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let source = depth.working_copy(img);
+    let (width, height) = source.dimensions();
+
+    let out = match method {
+        SeamlessMethod::Blend => {
+            // how much of the original to keep along one axis: none at the edges, where the
+            // shifted copy (which is continuous across them) takes over
+            let ramp = |position: u32, size: u32| {
+                let from_edge = (position as f32 + 0.5) / size as f32;
+                let from_edge = from_edge.min(1.0 - from_edge);
+                let reach = (overlap / 2.0).clamp(f32::EPSILON, 0.5);
+                let t = (from_edge / reach).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            };
+            let mix = |original: &Rgba<f32>, shifted: &Rgba<f32>, keep: f32| {
+                Rgba([0, 1, 2, 3].map(|c| shifted[c] + (original[c] - shifted[c]) * keep))
+            };
+            // one axis at a time, so the seams of the copy shifted across are hidden by the
+            // original before the copy shifted down is mixed in
+            let across = Rgba32FImage::from_fn(width, height, |x, y| {
+                let shifted = source.get_pixel((x + width / 2) % width, y);
+                mix(source.get_pixel(x, y), shifted, ramp(x, width))
+            });
+            Rgba32FImage::from_fn(width, height, |x, y| {
+                let shifted = across.get_pixel(x, (y + height / 2) % height);
+                mix(across.get_pixel(x, y), shifted, ramp(y, height))
+            })
+        }
+        SeamlessMethod::Mirror => Rgba32FImage::from_fn(width * 2, height * 2, |x, y| {
+            let x = if x < width { x } else { width * 2 - 1 - x };
+            let y = if y < height { y } else { height * 2 - 1 - y };
+            *source.get_pixel(x, y)
+        }),
+    };
+    depth.restore(out, had_alpha)
+}
+
+/// Repeat the image `count` times across and down, to **preview** how it tiles.
+pub fn tile_preview(img: DynamicImage, count: u32) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let source = depth.working_copy(img);
+    let (width, height) = source.dimensions();
+    let out = Rgba32FImage::from_fn(width * count, height * count, |x, y| {
+        *source.get_pixel(x % width, y % height)
+    });
+    depth.restore(out, had_alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    /// The largest difference between neighbouring pixels, with the image wrapped around.
+    fn largest_step(img: &RgbImage) -> (u8, u8) {
+        let (width, height) = img.dimensions();
+        let step = |a: &image::Rgb<u8>, b: &image::Rgb<u8>| {
+            (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap()
+        };
+        let (mut across, mut down) = (0, 0);
+        for y in 0..height {
+            for x in 0..width {
+                let here = img.get_pixel(x, y);
+                across = across.max(step(here, img.get_pixel((x + 1) % width, y)));
+                down = down.max(step(here, img.get_pixel(x, (y + 1) % height)));
+            }
+        }
+        (across, down)
+    }
+
+    #[test]
+    fn blend_has_no_seams() {
+        // a ramp in each direction, so the source jumps by 252 at both wrapped edges
+        let source = RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 0]));
+        assert!(largest_step(&source) >= (250, 230));
+
+        for overlap in [0.25, 0.5, 1.0] {
+            let img = seamless(
+                DynamicImage::ImageRgb8(source.clone()),
+                SeamlessMethod::Blend,
+                overlap,
+            );
+            let (across, down) = largest_step(&img.into_rgb8());
+            assert!(across <= 40 && down <= 40, "{overlap}: {across} {down}");
+        }
+    }
+
+    #[test]
+    fn mirror_doubles_the_size() {
+        let source = RgbImage::from_fn(5, 3, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        let img = seamless(DynamicImage::ImageRgb8(source), SeamlessMethod::Mirror, 0.5);
+        let img = img.into_rgb8();
+        assert_eq!(img.dimensions(), (10, 6));
+        assert_eq!(img.get_pixel(9, 5), img.get_pixel(0, 0));
+    }
+}
@@ -0,0 +1,283 @@
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+
+use image::io::Reader;
+use image::{DynamicImage, ImageFormat};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::output;
+use crate::recipe::OperationSpec;
+use crate::registry::{Registry, Shape, Step};
+use crate::shell;
+
+/// Where the server listens and how much it accepts.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// address to listen on, e.g. `127.0.0.1:8080`
+    pub address: String,
+    /// requests processed at once; any more wait until a worker is free
+    pub workers: usize,
+    /// largest upload accepted, in bytes
+    pub max_upload: u64,
+    /// largest image accepted, in pixels, whether uploaded or made by an operation
+    pub max_pixels: u64,
+}
+
+/// Applies the operations to an upload; see `serve`.
+type Process = dyn Fn(DynamicImage, Vec<Step>, ImageFormat) -> Result<DynamicImage, String>;
+
+/// Why a request failed: the HTTP status and a message for the client.
+type Failure = (u16, String);
+
+/// Serve `POST /process` until the process is killed.
+///
+/// The request body is the image, and the operations are given in the query string, either
+/// one `op` parameter per operation (`?op=blur+2&op=rotate+left`) or all at once as a JSON
+/// list (`?ops=["blur 2","rotate left"]`).  `format` chooses the format of the response
+/// (e.g. `?format=jpg`), which otherwise matches the upload.
+///
+/// Operations are looked up in `registry`.  Before any are run, each is checked against the
+/// size of the image it will get, and the request is refused if the image would grow past
+/// `max_pixels` along the way or an operation would be too costly (see `Action::check_cost`).
+/// `process` is then given the decoded image, the operations and the format the result will
+/// be encoded in, and returns the result or a message explaining what went wrong.
+pub fn serve(
+    config: &ServerConfig,
+    registry: Registry,
+    process: impl Fn(DynamicImage, Vec<Step>, ImageFormat) -> Result<DynamicImage, String>
+        + Send
+        + Sync
+        + 'static,
+) -> Result<(), String> {
+    let server = Server::http(&config.address).map_err(|e| format!("{}: {e}", config.address))?;
+    let server = Arc::new(server);
+    let registry = Arc::new(registry);
+    let process = Arc::new(process);
+    eprintln!("Listening on http://{}/process", config.address);
+
+    let workers: Vec<_> = (0..config.workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let registry = Arc::clone(&registry);
+            let process = Arc::clone(&process);
+            let config = config.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, &config, &registry, &*process);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+/// Answer a single request and log the outcome.
+fn handle(mut request: Request, config: &ServerConfig, registry: &Registry, process: &Process) {
+    let response = match process_request(&mut request, config, registry, process) {
+        Ok((format, bytes)) => Response::from_data(bytes).with_header(
+            Header::from_bytes("Content-Type", format.to_mime_type())
+                .expect("MIME types are valid header values"),
+        ),
+        Err((status, message)) => Response::from_string(message + "\n").with_status_code(status),
+    };
+    eprintln!(
+        "{} {} {}",
+        request.method(),
+        request.url(),
+        response.status_code().0
+    );
+    let _ = request.respond(response);
+}
+
+fn process_request(
+    request: &mut Request,
+    config: &ServerConfig,
+    registry: &Registry,
+    process: &Process,
+) -> Result<(ImageFormat, Vec<u8>), Failure> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    if path != "/process" {
+        return Err((404, format!("{path}: not found; POST images to /process")));
+    }
+    if *request.method() != Method::Post {
+        return Err((405, format!("{path}: images must be sent with POST")));
+    }
+
+    let mut steps = Vec::new();
+    let mut format = None;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "op" => {
+                let words = shell::split_words(&value).map_err(|e| (400, e))?;
+                steps.push(parse_step(registry, &words)?);
+            }
+            "ops" => {
+                let specs: Vec<OperationSpec> =
+                    serde_json::from_str(&value).map_err(|e| (400, format!("ops: {e}")))?;
+                for spec in specs {
+                    let words = spec.into_words().map_err(|e| (400, e))?;
+                    steps.push(parse_step(registry, &words)?);
+                }
+            }
+            "format" => {
+                format = Some(
+                    ImageFormat::from_extension(value.as_ref())
+                        .filter(ImageFormat::can_write)
+                        .ok_or_else(|| (400, format!("format: can't write {value} images")))?,
+                );
+            }
+            _ => return Err((400, format!("unknown parameter {key}"))),
+        }
+    }
+
+    let too_large = || {
+        (
+            413,
+            format!("uploads are limited to {} bytes", config.max_upload),
+        )
+    };
+    if request
+        .body_length()
+        .is_some_and(|length| length as u64 > config.max_upload)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(config.max_upload + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+    if body.len() as u64 > config.max_upload {
+        return Err(too_large());
+    }
+
+    let (img, input_format) = decode(&body, config.max_pixels)?;
+    let format = format
+        .or(input_format.filter(ImageFormat::can_write))
+        .unwrap_or(ImageFormat::Png);
+    check_steps(&steps, Shape::of(&img), config.max_pixels)?;
+    let indexed = steps.iter().any(Step::makes_palette);
+
+    let img = panic::catch_unwind(AssertUnwindSafe(|| process(img, steps, format)))
+        .map_err(|_| (500, "processing the image failed".to_string()))?
+        .map_err(|e| (400, e))?;
+    let bytes = output::encode_image(&img, format, indexed).map_err(|e| (500, e))?;
+    Ok((format, bytes))
+}
+
+/// Parse an operation from the request, refusing any that read files on the server.
+fn parse_step(registry: &Registry, words: &[String]) -> Result<Step, Failure> {
+    let step = registry.parse(words).map_err(|e| (400, e.to_string()))?;
+    if step.reads_files() {
+        return Err((
+            400,
+            format!("{}: can't read files on the server", step.name()),
+        ));
+    }
+    Ok(step)
+}
+
+/// Check `steps` against an image of the shape `input` without running them, refusing any that
+/// are too costly or would make an image of more than `max_pixels` pixels.
+fn check_steps(steps: &[Step], input: Shape, max_pixels: u64) -> Result<(), Failure> {
+    let mut shape = input;
+    for step in steps {
+        let name = step.name();
+        step.check_cost()
+            .map_err(|e| (400, format!("{name}: {e}")))?;
+        shape = step
+            .check(shape)
+            .map_err(|e| (400, format!("{name}: {e}")))?;
+        if shape.pixels() > max_pixels {
+            return Err((
+                413,
+                format!(
+                    "{name}: the result would be {}x{}; images are limited to {max_pixels} pixels",
+                    shape.width, shape.height
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Decode an uploaded image, refusing any with more than `max_pixels` pixels before
+/// decoding them.
+fn decode(bytes: &[u8], max_pixels: u64) -> Result<(DynamicImage, Option<ImageFormat>), Failure> {
+    let reader = || {
+        Reader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|e| (400, e.to_string()))
+    };
+
+    let (width, height) = reader()?
+        .into_dimensions()
+        .map_err(|e| (400, e.to_string()))?;
+    if width as u64 * height as u64 > max_pixels {
+        return Err((
+            413,
+            format!("the image is {width}x{height}; images are limited to {max_pixels} pixels"),
+        ));
+    }
+
+    let reader = reader()?;
+    let format = reader.format();
+    let img = reader.decode().map_err(|e| (400, e.to_string()))?;
+    Ok((img, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_http::TestRequest;
+
+    /// Post a 2x2 image with the query string `query`, allowing at most `max_pixels` pixels.
+    fn post(query: &str, max_pixels: u64) -> Result<(ImageFormat, Vec<u8>), Failure> {
+        let config = ServerConfig {
+            address: String::new(),
+            workers: 1,
+            max_upload: 1000,
+            max_pixels,
+        };
+        let mut request = TestRequest::new()
+            .with_method(Method::Post)
+            .with_path(&format!("/process?{query}"))
+            .with_body("P3\n2 2\n255\n0 0 0 255 255 255 255 255 255 0 0 0\n")
+            .into();
+        let process =
+            |img, steps: Vec<Step>, _| steps.iter().try_fold(img, |img, step| step.apply(img));
+        process_request(&mut request, &config, &Registry::builtin(), &process)
+    }
+
+    #[test]
+    fn small_requests_are_processed() {
+        let (format, bytes) = post("op=invert&format=png", 100).unwrap();
+        assert_eq!(format, ImageFormat::Png);
+        let img = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(img.to_rgb8().get_pixel(0, 0).0, [255, 255, 255]);
+    }
+
+    #[test]
+    fn images_made_by_operations_are_limited() {
+        let (status, _) = post("op=plasma+--size+5000x5000", 100).unwrap_err();
+        assert_eq!(status, 413);
+        let (status, _) = post("op=tile-preview+--count+8", 100).unwrap_err();
+        assert_eq!(status, 413);
+        assert!(post("op=tile-preview+--count+5", 100).is_ok());
+    }
+
+    #[test]
+    fn costly_arguments_are_refused() {
+        for op in ["blur+1e30+--method+box", "denoise+nl-means+--radius+100"] {
+            let (status, _) = post(&format!("op={op}"), 100).unwrap_err();
+            assert_eq!(status, 400, "{op}");
+        }
+    }
+}
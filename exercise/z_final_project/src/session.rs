@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// File name used for sessions when no other is given.
+pub const DEFAULT_FILE: &str = "edit.mirage.json";
+
+/// A non-destructive edit: the original image plus the operations to apply to it.
+///
+/// Operations are kept as the words typed on the command line (e.g. `["blur", "2"]`), so the
+/// file stays readable and can be edited by hand.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// the original image, which is only ever read
+    pub source: PathBuf,
+    /// operations in the order they are applied
+    pub operations: Vec<Vec<String>>,
+    /// operations taken off by `undo`, most recent last, so they can be redone
+    #[serde(default)]
+    pub undone: Vec<Vec<String>>,
+}
+
+impl Session {
+    /// Start a session for the image at `source`.
+    pub fn new(source: &str) -> Result<Session, String> {
+        let source = Path::new(source)
+            .canonicalize()
+            .map_err(|e| format!("{source}: {e}"))?;
+        Ok(Session {
+            source,
+            operations: Vec::new(),
+            undone: Vec::new(),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Session, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        serde_json::from_str(&json).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json + "\n").map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// Add an operation to the end.  This forgets anything that could have been redone.
+    pub fn add(&mut self, operation: Vec<String>) {
+        self.operations.push(operation);
+        self.undone.clear();
+    }
+
+    /// Take the last operation off, returning it, or `None` if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<&[String]> {
+        let operation = self.operations.pop()?;
+        self.undone.push(operation);
+        self.undone.last().map(Vec::as_slice)
+    }
+
+    /// Put back the operation most recently undone, returning it.
+    pub fn redo(&mut self) -> Option<&[String]> {
+        let operation = self.undone.pop()?;
+        self.operations.push(operation);
+        self.operations.last().map(Vec::as_slice)
+    }
+
+    /// Remove the operation at `position` (counting from 1), returning it.
+    pub fn remove(&mut self, position: usize) -> Option<Vec<String>> {
+        if position == 0 || position > self.operations.len() {
+            return None;
+        }
+        Some(self.operations.remove(position - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn session() -> Session {
+        Session {
+            source: PathBuf::from("photo.png"),
+            operations: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut session = session();
+        assert_eq!(session.undo(), None);
+        session.add(words("blur 2"));
+        session.add(words("invert"));
+
+        assert_eq!(session.undo(), Some(&words("invert")[..]));
+        assert_eq!(session.undo(), Some(&words("blur 2")[..]));
+        assert_eq!(session.undo(), None);
+        assert!(session.operations.is_empty());
+
+        assert_eq!(session.redo(), Some(&words("blur 2")[..]));
+        assert_eq!(session.redo(), Some(&words("invert")[..]));
+        assert_eq!(session.redo(), None);
+        assert_eq!(session.operations, [words("blur 2"), words("invert")]);
+    }
+
+    #[test]
+    fn adding_forgets_what_could_be_redone() {
+        let mut session = session();
+        session.add(words("blur 2"));
+        session.undo();
+        session.add(words("grayscale"));
+        assert_eq!(session.redo(), None);
+        assert_eq!(session.operations, [words("grayscale")]);
+    }
+
+    #[test]
+    fn remove_counts_from_one() {
+        let mut session = session();
+        session.add(words("blur 2"));
+        session.add(words("invert"));
+        assert_eq!(session.remove(0), None);
+        assert_eq!(session.remove(3), None);
+        assert_eq!(session.remove(1), Some(words("blur 2")));
+        assert_eq!(session.operations, [words("invert")]);
+    }
+
+    #[test]
+    fn saved_sessions_load_the_same() {
+        let mut session = session();
+        session.add(words("text 'two words'"));
+        session.add(words("invert"));
+        session.undo();
+
+        let path = std::env::temp_dir().join(format!("mirage-session-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        session.save(path).unwrap();
+        let loaded = Session::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded, Ok(session));
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+/// Split a line typed into the shell into words, like a POSIX shell would.
+///
+/// Words are separated by whitespace.  Single quotes keep everything up to the next single
+/// quote, double quotes keep everything up to the next double quote except that a backslash
+/// escapes the next character, and an unquoted backslash escapes the next character.
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("missing closing '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err("missing closing \"".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("missing closing \"".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// What `undo` in the shell goes back through: the most recent `limit` entries, oldest
+/// forgotten first.
+pub struct History<T> {
+    entries: VecDeque<T>,
+    limit: usize,
+}
+
+impl<T> History<T> {
+    pub fn new(limit: usize) -> History<T> {
+        History {
+            entries: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Remember `entry`, forgetting the oldest entry if there are now more than the limit.
+    pub fn push(&mut self, entry: T) {
+        self.entries.push_back(entry);
+        if self.entries.len() > self.limit {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Take back the most recent entry.
+    pub fn pop(&mut self) -> Option<T> {
+        self.entries.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        split_words(line).unwrap()
+    }
+
+    #[test]
+    fn words_are_split_on_whitespace() {
+        assert_eq!(words("  blur   2 "), ["blur", "2"]);
+        assert_eq!(words("rotate\tleft\n"), ["rotate", "left"]);
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_words_together() {
+        assert_eq!(words("text 'Hello, world'"), ["text", "Hello, world"]);
+        assert_eq!(words(r#"text "say \"hi\"""#), ["text", r#"say "hi""#]);
+        assert_eq!(words(r"text 'a\b'"), ["text", r"a\b"]);
+        assert_eq!(words("text ''"), ["text", ""]);
+        assert_eq!(words(r#"a'b'"c d""#), ["abc d"]);
+    }
+
+    #[test]
+    fn backslashes_escape_the_next_character() {
+        assert_eq!(words(r"text Hello\ world"), ["text", "Hello world"]);
+        assert_eq!(words(r"text \'"), ["text", "'"]);
+        assert_eq!(words(r"text \\"), ["text", r"\"]);
+    }
+
+    #[test]
+    fn unclosed_quotes_are_errors() {
+        assert!(split_words("text 'Hello").is_err());
+        assert!(split_words(r#"text "Hello"#).is_err());
+        assert!(split_words(r#"text "Hello\"#).is_err());
+    }
+
+    #[test]
+    fn history_forgets_the_oldest_entries() {
+        let mut history = History::new(3);
+        assert!(history.pop().is_none());
+        for i in 1..=5 {
+            history.push(i);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.pop(), Some(5));
+        assert_eq!(history.pop(), Some(4));
+        assert_eq!(history.pop(), Some(3));
+        assert_eq!(history.pop(), None);
+        assert_eq!(history.len(), 0);
+    }
+}
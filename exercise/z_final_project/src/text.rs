@@ -0,0 +1,234 @@
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use image::{DynamicImage, GrayImage, Luma, Rgba, Rgba32FImage, RgbaImage};
+
+use crate::depth::Depth;
+use crate::gravity::Gravity;
+use crate::overlay::{composite, BlendMode};
+
+/// The font used when no font file is supplied: DejaVu Sans Bold (see `fonts/LICENSE`).
+const BUNDLED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans-Bold.ttf");
+
+/// How text should look when it is drawn.
+pub struct TextStyle {
+    /// font size in pixels
+    pub size: f32,
+    /// fill colour of the letters
+    pub colour: Rgba<u8>,
+    /// width of the outline around each letter in pixels (0 for no outline)
+    pub outline_width: f32,
+    /// colour of the outline
+    pub outline_colour: Rgba<u8>,
+    /// offset of the drop shadow in pixels ((0, 0) for no shadow)
+    pub shadow_offset: (i64, i64),
+    /// colour of the drop shadow
+    pub shadow_colour: Rgba<u8>,
+}
+
+/// Load a TrueType/OpenType font from `path`, or the bundled font if no path is given.
+pub fn load_font(path: Option<&str>) -> Result<FontArc, String> {
+    match path {
+        None => FontArc::try_from_slice(BUNDLED_FONT).map_err(|e| e.to_string()),
+        Some(path) => {
+            let bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+            FontArc::try_from_vec(bytes).map_err(|e| format!("{path}: {e}"))
+        }
+    }
+}
+
+/// Draw **text** on the image, placed according to `gravity` and `offset`.
+pub fn text(
+    img: DynamicImage,
+    text: &str,
+    font: &FontArc,
+    style: &TextStyle,
+    gravity: Gravity,
+    offset: (i64, i64),
+) -> DynamicImage {
+    let depth = Depth::of(&img);
+    let had_alpha = img.color().has_alpha();
+    let mut base = depth.working_copy(img);
+
+    let layer = depth.working_copy(DynamicImage::ImageRgba8(render_text(text, font, style)));
+    let (x, y) = gravity.position(base.dimensions(), layer.dimensions(), offset);
+    composite(&mut base, &layer, x, y, 1.0, BlendMode::Normal);
+
+    depth.restore(base, had_alpha)
+}
+
+/// Render `text` (which may contain several lines) to a transparent image just big enough to
+/// hold it, including its outline and drop shadow.
+pub fn render_text(text: &str, font: &FontArc, style: &TextStyle) -> RgbaImage {
+    let outline = style.outline_width.max(0.0);
+    let pad = outline.ceil() as u32 + 1;
+    let glyphs = coverage_mask(text, font, style.size, pad);
+    let silhouette = if outline > 0.0 {
+        dilate(&glyphs, outline)
+    } else {
+        glyphs.clone()
+    };
+
+    let (mask_w, mask_h) = glyphs.dimensions();
+    let (shadow_x, shadow_y) = style.shadow_offset;
+    let text_x = (-shadow_x).max(0);
+    let text_y = (-shadow_y).max(0);
+    let mut layer = Rgba32FImage::new(
+        mask_w + shadow_x.unsigned_abs() as u32,
+        mask_h + shadow_y.unsigned_abs() as u32,
+    );
+
+    if style.shadow_offset != (0, 0) {
+        let shadow = colour_mask(&silhouette, style.shadow_colour);
+        composite(
+            &mut layer,
+            &shadow,
+            shadow_x.max(0),
+            shadow_y.max(0),
+            1.0,
+            BlendMode::Normal,
+        );
+    }
+    if outline > 0.0 {
+        let outline = colour_mask(&silhouette, style.outline_colour);
+        composite(&mut layer, &outline, text_x, text_y, 1.0, BlendMode::Normal);
+    }
+    let fill = colour_mask(&glyphs, style.colour);
+    composite(&mut layer, &fill, text_x, text_y, 1.0, BlendMode::Normal);
+
+    DynamicImage::ImageRgba32F(layer).into_rgba8()
+}
+
+/// Rasterise `text` to a grayscale coverage mask with `pad` empty pixels around every edge.
+fn coverage_mask(text: &str, font: &FontArc, size: f32, pad: u32) -> GrayImage {
+    let scale = PxScale::from(size);
+    let scaled = font.as_scaled(scale);
+    let line_height = scaled.height() + scaled.line_gap();
+
+    let mut glyphs = Vec::new();
+    let mut width: f32 = 0.0;
+    let mut lines = 0;
+    for (line_no, line) in text.lines().enumerate() {
+        let mut caret = point(0.0, line_no as f32 * line_height + scaled.ascent());
+        let mut previous = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret.x += scaled.kern(previous, id);
+            }
+            glyphs.push(id.with_scale_and_position(scale, caret));
+            caret.x += scaled.h_advance(id);
+            previous = Some(id);
+        }
+        width = width.max(caret.x);
+        lines += 1;
+    }
+    let height = lines.max(1) as f32 * line_height;
+
+    let mut mask = GrayImage::new(
+        width.ceil() as u32 + 2 * pad,
+        height.ceil() as u32 + 2 * pad,
+    );
+    let (mask_w, mask_h) = mask.dimensions();
+    for glyph in glyphs {
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let px = bounds.min.x as i64 + x as i64 + pad as i64;
+                let py = bounds.min.y as i64 + y as i64 + pad as i64;
+                if px < 0 || py < 0 || px >= mask_w as i64 || py >= mask_h as i64 {
+                    return;
+                }
+                let pixel = mask.get_pixel_mut(px as u32, py as u32);
+                let value = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+                pixel[0] = pixel[0].max(value);
+            });
+        }
+    }
+
+    mask
+}
+
+/// Grow a coverage mask outwards by `radius` pixels, used to draw outlines.
+fn dilate(mask: &GrayImage, radius: f32) -> GrayImage {
+    let reach = radius.ceil() as i64;
+    let (width, height) = mask.dimensions();
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let mut value = 0;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                if distance > radius + 0.5 {
+                    continue;
+                }
+                let sx = x as i64 + dx;
+                let sy = y as i64 + dy;
+                if sx < 0 || sy < 0 || sx >= width as i64 || sy >= height as i64 {
+                    continue;
+                }
+                // soften the outer edge of the outline a little
+                let falloff = (radius + 0.5 - distance).min(1.0);
+                let sample = mask.get_pixel(sx as u32, sy as u32)[0] as f32 * falloff;
+                value = value.max(sample as u8);
+            }
+        }
+        Luma([value])
+    })
+}
+
+/// Turn a coverage mask into an image filled with `colour`, using the mask as its alpha.
+fn colour_mask(mask: &GrayImage, colour: Rgba<u8>) -> Rgba32FImage {
+    let [r, g, b, a] = colour.0.map(|c| c as f32 / 255.0);
+    Rgba32FImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let coverage = mask.get_pixel(x, y)[0] as f32 / 255.0;
+        Rgba([r, g, b, a * coverage])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    use crate::Registry;
+
+    fn style() -> TextStyle {
+        TextStyle {
+            size: 32.0,
+            colour: Rgba([255, 255, 255, 255]),
+            outline_width: 0.0,
+            outline_colour: Rgba([0, 0, 0, 255]),
+            shadow_offset: (0, 0),
+            shadow_colour: Rgba([0, 0, 0, 128]),
+        }
+    }
+
+    #[test]
+    fn text_is_drawn_where_it_is_placed() {
+        let font = load_font(None).unwrap();
+        let style = style();
+        let (width, height) = render_text("Hi", &font, &style).dimensions();
+        let blank = DynamicImage::ImageRgb8(RgbImage::new(200, 100));
+
+        let drawn = text(blank, "Hi", &font, &style, Gravity::NorthWest, (10, 20)).into_rgb8();
+        let inside =
+            |x: u32, y: u32| (10..10 + width).contains(&x) && (20..20 + height).contains(&y);
+        let mut changed = 0;
+        for (x, y, pixel) in drawn.enumerate_pixels() {
+            if pixel.0 != [0, 0, 0] {
+                assert!(inside(x, y), "{x},{y} is outside the text");
+                changed += 1;
+            }
+        }
+        assert!(changed > 50, "only {changed} pixels were drawn");
+    }
+
+    #[test]
+    fn sizes_must_be_positive() {
+        let registry = Registry::builtin();
+        let img = DynamicImage::new_rgb8(10, 10);
+        for size in ["0", "0.5", "NaN"] {
+            let step = registry.parse(&["text", "Hi", "--size", size]).unwrap();
+            assert!(step.apply(img.clone()).is_err(), "{size}");
+        }
+    }
+}
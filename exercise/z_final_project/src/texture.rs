@@ -0,0 +1,462 @@
+use std::f32::consts::{PI, TAU};
+
+use clap::ValueEnum;
+use image::{DynamicImage, ImageBuffer, Luma, Rgb, Rgba};
+
+use crate::montage::parse_size;
+use crate::progress::Progress;
+
+/// Largest width or height the generators make, the biggest texture most GPUs take.
+pub const MAX_SIDE: u32 = 16384;
+
+/// Parse a texture size given as `WIDTHxHEIGHT`, neither of which may be over `MAX_SIDE`.
+pub fn parse_texture_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = parse_size(s)?;
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Err(format!(
+            "'{s}' is too large; textures are at most {MAX_SIDE}x{MAX_SIDE}"
+        ));
+    }
+    Ok((width, height))
+}
+
+/// Kinds of noise `noise` can generate.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// random values on a grid, smoothly interpolated; blocky at low octaves
+    Value,
+    /// classic Perlin gradient noise
+    Perlin,
+    /// simplex noise, with fewer grid artefacts than Perlin
+    Simplex,
+    /// Worley (cellular) noise: the distance to the nearest of a scatter of points
+    Worley,
+}
+
+/// The shape of a noise texture.
+pub struct NoiseSettings {
+    /// features across the width in the first octave; the count down keeps them square
+    pub cells: u32,
+    /// layers of noise, each with twice as many features as the one before
+    pub octaves: u32,
+    /// how much each octave counts compared to the one before
+    pub persistence: f32,
+    pub seed: u64,
+}
+
+/// Directions `stripes` can run in.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StripeDirection {
+    Horizontal,
+    Vertical,
+    /// from the bottom left to the top right
+    Diagonal,
+}
+
+/// Generate grayscale **noise** of `size`.
+///
+/// Every kind of noise wraps around at the edges of the image, so the result tiles seamlessly.
+pub fn noise(
+    kind: NoiseKind,
+    (width, height): (u32, u32),
+    settings: &NoiseSettings,
+) -> DynamicImage {
+    let cells_x = settings.cells.max(1);
+    let cells_y = ((cells_x as f32 * height as f32 / width as f32).round() as u32).max(1);
+    let mut progress = Progress::new("noise", height as u64);
+
+    let img = ImageBuffer::from_fn(width, height, |x, y| {
+        if x == 0 {
+            progress.set(y as u64);
+        }
+        // position as a fraction of the image, sampled at the centre of the pixel
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut amplitudes = 0.0;
+        for octave in 0..settings.octaves.max(1) {
+            let scale = 1 << octave.min(16);
+            let period = (cells_x.saturating_mul(scale), cells_y.saturating_mul(scale));
+            let seed = hash(settings.seed, &[octave as i64]);
+            let (px, py) = (u * period.0 as f32, v * period.1 as f32);
+            let n = match kind {
+                NoiseKind::Value => value_noise(px, py, period, seed),
+                NoiseKind::Perlin => perlin_noise(px, py, period, seed),
+                NoiseKind::Simplex => {
+                    // round a torus in 4D, so both directions wrap without a seam
+                    let (rx, ry) = (period.0 as f32 / TAU, period.1 as f32 / TAU);
+                    let (ax, ay) = (u * TAU, v * TAU);
+                    simplex_noise_4d(
+                        [rx * ax.cos(), rx * ax.sin(), ry * ay.cos(), ry * ay.sin()],
+                        seed,
+                    )
+                }
+                NoiseKind::Worley => worley_noise(px, py, period, seed),
+            };
+            total += n * amplitude;
+            amplitudes += amplitude;
+            amplitude *= settings.persistence;
+        }
+
+        let n = (0.5 + 0.5 * total / amplitudes).clamp(0.0, 1.0);
+        Luma([(n * 65535.0).round() as u16])
+    });
+    DynamicImage::ImageLuma16(img)
+}
+
+/// Generate a **checkerboard** of `size` with `squares` across.
+///
+/// The number of squares down is chosen to keep them close to square, and is even whenever
+/// `squares` is, so a board with an even number of squares tiles.
+pub fn checkerboard(
+    (width, height): (u32, u32),
+    squares: u32,
+    colour: Rgba<u8>,
+    background: Rgba<u8>,
+) -> DynamicImage {
+    let across = squares.max(1);
+    let mut down = ((across as f32 * height as f32 / width as f32).round() as u32).max(1);
+    if across.is_multiple_of(2) && !down.is_multiple_of(2) {
+        down += 1;
+    }
+
+    let img = ImageBuffer::from_fn(width, height, |x, y| {
+        let column = (x as u64 * across as u64 / width as u64) as u32;
+        let row = (y as u64 * down as u64 / height as u64) as u32;
+        if (column + row).is_multiple_of(2) {
+            colour
+        } else {
+            background
+        }
+    });
+    DynamicImage::ImageRgba8(img)
+}
+
+/// Generate `count` **stripes** of `colour` on `background`.
+///
+/// `softness` runs from 0.0 for hard edges to 1.0 for a smooth wave between the colours.
+/// Diagonal stripes cross the image the same number of times in both directions, so every
+/// direction tiles.
+pub fn stripes(
+    (width, height): (u32, u32),
+    count: u32,
+    direction: StripeDirection,
+    softness: f32,
+    colour: Rgba<u8>,
+    background: Rgba<u8>,
+) -> DynamicImage {
+    let count = count.max(1) as f32;
+    let softness = softness.clamp(0.0, 1.0);
+
+    let img = ImageBuffer::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+        let position = match direction {
+            StripeDirection::Horizontal => v,
+            StripeDirection::Vertical => u,
+            StripeDirection::Diagonal => u + v,
+        } * count;
+
+        // 1.0 in the middle of a stripe, falling to 0.0 half way to the next one
+        let wave = 0.5 + 0.5 * (TAU * position).cos();
+        let coverage = if softness > 0.0 {
+            smoothstep(0.5 - softness / 2.0, 0.5 + softness / 2.0, wave)
+        } else if wave >= 0.5 {
+            1.0
+        } else {
+            0.0
+        };
+
+        let mix = |a: u8, b: u8| {
+            let c = b as f32 + (a as f32 - b as f32) * coverage;
+            (c * 257.0).round() as u16
+        };
+        Rgba([0, 1, 2, 3].map(|c| mix(colour[c], background[c])))
+    });
+    DynamicImage::ImageRgba16(img)
+}
+
+/// Generate a colourful **plasma** of `size`, made of overlapping waves chosen by `seed`.
+///
+/// The waves repeat a whole number of times across the image, so the plasma tiles.
+pub fn plasma((width, height): (u32, u32), seed: u64) -> DynamicImage {
+    const WAVES: usize = 4;
+
+    let random = |index: i64| unit(hash(seed, &[index]));
+    // cycles across and down for each wave, between -3 and 3 and never both zero
+    let waves: Vec<(f32, f32, f32)> = (0..WAVES as i64)
+        .map(|wave| {
+            let mut fx = (random(wave * 3) * 7.0) as i32 - 3;
+            let fy = (random(wave * 3 + 1) * 7.0) as i32 - 3;
+            if fx == 0 && fy == 0 {
+                fx = 1 + wave as i32 % 3;
+            }
+            (fx as f32, fy as f32, random(wave * 3 + 2) * TAU)
+        })
+        .collect();
+    let palette = [0, 1, 2].map(|channel| random(100 + channel));
+
+    let img = ImageBuffer::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / width as f32;
+        let v = (y as f32 + 0.5) / height as f32;
+        let value = waves
+            .iter()
+            .map(|(fx, fy, phase)| (TAU * (fx * u + fy * v) + phase).sin())
+            .sum::<f32>()
+            / WAVES as f32;
+
+        Rgb(palette.map(|offset| {
+            let c = 0.5 + 0.5 * (PI * (2.0 * value + 2.0 * offset)).cos();
+            (c * 65535.0).round() as u16
+        }))
+    });
+    DynamicImage::ImageRgb16(img)
+}
+
+/// Value noise at `(x, y)` on a grid that repeats every `period` cells, between -1.0 and 1.0.
+fn value_noise(x: f32, y: f32, period: (u32, u32), seed: u64) -> f32 {
+    let corner = |cx: i64, cy: i64| {
+        let (cx, cy) = wrap(cx, cy, period);
+        unit(hash(seed, &[cx, cy])) * 2.0 - 1.0
+    };
+    interpolate_grid(x, y, |cx, cy, _, _| corner(cx, cy))
+}
+
+/// Perlin noise at `(x, y)` on a grid that repeats every `period` cells, between about -1.0
+/// and 1.0.
+fn perlin_noise(x: f32, y: f32, period: (u32, u32), seed: u64) -> f32 {
+    let corner = |cx: i64, cy: i64, dx: f32, dy: f32| {
+        let (cx, cy) = wrap(cx, cy, period);
+        let angle = unit(hash(seed, &[cx, cy])) * TAU;
+        angle.cos() * dx + angle.sin() * dy
+    };
+    // the largest a 2D gradient noise can reach is 1/sqrt(2)
+    interpolate_grid(x, y, corner) * std::f32::consts::SQRT_2
+}
+
+/// Interpolate smoothly between the values `corner` gives for the four grid points around
+/// `(x, y)`.  `corner` is given each point and the offset from it to `(x, y)`.
+fn interpolate_grid(x: f32, y: f32, corner: impl Fn(i64, i64, f32, f32) -> f32) -> f32 {
+    let (cx, cy) = (x.floor(), y.floor());
+    let (fx, fy) = (x - cx, y - cy);
+    let (cx, cy) = (cx as i64, cy as i64);
+
+    let top = lerp(
+        corner(cx, cy, fx, fy),
+        corner(cx + 1, cy, fx - 1.0, fy),
+        fade(fx),
+    );
+    let bottom = lerp(
+        corner(cx, cy + 1, fx, fy - 1.0),
+        corner(cx + 1, cy + 1, fx - 1.0, fy - 1.0),
+        fade(fx),
+    );
+    lerp(top, bottom, fade(fy))
+}
+
+/// Worley noise at `(x, y)`: the distance to the nearest of one point scattered in each cell
+/// of a grid that repeats every `period` cells, scaled to between -1.0 and 1.0.
+fn worley_noise(x: f32, y: f32, period: (u32, u32), seed: u64) -> f32 {
+    let (cx, cy) = (x.floor() as i64, y.floor() as i64);
+    let mut nearest = f32::MAX;
+    for ny in cy - 1..=cy + 1 {
+        for nx in cx - 1..=cx + 1 {
+            let (wx, wy) = wrap(nx, ny, period);
+            let h = hash(seed, &[wx, wy]);
+            let point_x = nx as f32 + unit(h);
+            let point_y = ny as f32 + unit(hash(h, &[1]));
+            nearest = nearest.min((point_x - x).hypot(point_y - y));
+        }
+    }
+    nearest.min(1.0) * 2.0 - 1.0
+}
+
+/// 4D simplex noise (after Stefan Gustavson's "Simplex noise demystified"), between about
+/// -1.0 and 1.0.
+fn simplex_noise_4d(p: [f32; 4], seed: u64) -> f32 {
+    let sqrt5 = 5.0_f32.sqrt();
+    let skew = (sqrt5 - 1.0) / 4.0;
+    let unskew = (5.0 - sqrt5) / 20.0;
+
+    // the simplex cell the point is in, and the offset from its first corner
+    let s = p.iter().sum::<f32>() * skew;
+    let cell = p.map(|c| (c + s).floor());
+    let t = cell.iter().sum::<f32>() * unskew;
+    let offset: [f32; 4] = std::array::from_fn(|i| p[i] - (cell[i] - t));
+
+    // the order of the offsets decides which way through the simplex the corners go
+    let mut rank = [0; 4];
+    for i in 0..4 {
+        for j in i + 1..4 {
+            if offset[i] > offset[j] {
+                rank[i] += 1;
+            } else {
+                rank[j] += 1;
+            }
+        }
+    }
+
+    let mut total = 0.0;
+    for corner in 0..5 {
+        let step: [i64; 4] = rank.map(|r| i64::from(corner > 0 && r >= 4 - corner));
+        let d: [f32; 4] =
+            std::array::from_fn(|i| offset[i] - step[i] as f32 + corner as f32 * unskew);
+        let falloff = 0.6 - d.iter().map(|c| c * c).sum::<f32>();
+        if falloff > 0.0 {
+            let index: [i64; 4] = std::array::from_fn(|i| cell[i] as i64 + step[i]);
+            let gradient = gradient_4d(hash(seed, &index));
+            let dot: f32 = (0..4).map(|i| gradient[i] * d[i]).sum();
+            total += falloff.powi(4) * dot;
+        }
+    }
+    27.0 * total
+}
+
+/// One of the 32 gradients for 4D simplex noise: the midpoints of the edges of a hypercube,
+/// with one coordinate zero and the others each 1 or -1.
+fn gradient_4d(h: u64) -> [f32; 4] {
+    let zero = (h % 4) as usize;
+    let signs = (h / 4) % 8;
+    let mut gradient = [0.0; 4];
+    let mut bit = 0;
+    for (i, c) in gradient.iter_mut().enumerate() {
+        if i != zero {
+            *c = if signs >> bit & 1 == 0 { 1.0 } else { -1.0 };
+            bit += 1;
+        }
+    }
+    gradient
+}
+
+/// Wrap grid coordinates into `0..period`.
+fn wrap(x: i64, y: i64, period: (u32, u32)) -> (i64, i64) {
+    (x.rem_euclid(period.0 as i64), y.rem_euclid(period.1 as i64))
+}
+
+/// Mix `seed` and `values` into a well-scrambled hash (the SplitMix64 finaliser).
+fn hash(seed: u64, values: &[i64]) -> u64 {
+    let mut h = seed ^ 0x9e37_79b9_7f4a_7c15;
+    for &value in values {
+        h ^= value as u64;
+        h = h.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+    h
+}
+
+/// A hash as a number in `0.0..1.0`.
+fn unit(h: u64) -> f32 {
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Perlin's quintic fade curve, which is flat at 0.0 and 1.0 so the grid doesn't show.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_sizes_are_limited() {
+        assert_eq!(parse_texture_size("16384x1"), Ok((16384, 1)));
+        assert!(parse_texture_size("16385x1").is_err());
+        assert!(parse_texture_size("1x100000").is_err());
+        assert!(parse_texture_size("0x10").is_err());
+    }
+
+    /// The biggest change between neighbouring pixels inside the image, and the biggest across
+    /// the seams where copies of it meet when tiled.
+    fn steps(img: &DynamicImage) -> (f32, f32) {
+        let img = img.to_rgba32f();
+        let (width, height) = img.dimensions();
+        let step = |a: (u32, u32), b: (u32, u32)| {
+            let (a, b) = (img.get_pixel(a.0, a.1), img.get_pixel(b.0, b.1));
+            (0..4).map(|c| (a[c] - b[c]).abs()).fold(0.0, f32::max)
+        };
+        let (mut inside, mut seams) = (0.0f32, 0.0f32);
+        for y in 0..height {
+            for x in 0..width {
+                let (right, down) = ((x + 1) % width, (y + 1) % height);
+                let across = step((x, y), (right, y));
+                let below = step((x, y), (x, down));
+                if right == 0 {
+                    seams = seams.max(across);
+                } else {
+                    inside = inside.max(across);
+                }
+                if down == 0 {
+                    seams = seams.max(below);
+                } else {
+                    inside = inside.max(below);
+                }
+            }
+        }
+        (inside, seams)
+    }
+
+    #[test]
+    fn textures_tile_seamlessly() {
+        let size = (64, 48);
+        let settings = NoiseSettings {
+            cells: 4,
+            octaves: 3,
+            persistence: 0.5,
+            seed: 7,
+        };
+        let (white, black) = (Rgba([255; 4]), Rgba([0, 0, 0, 255]));
+        let mut textures: Vec<(String, DynamicImage)> = [
+            NoiseKind::Value,
+            NoiseKind::Perlin,
+            NoiseKind::Simplex,
+            NoiseKind::Worley,
+        ]
+        .into_iter()
+        .map(|kind| (format!("{kind:?}"), noise(kind, size, &settings)))
+        .collect();
+        for direction in [
+            StripeDirection::Horizontal,
+            StripeDirection::Vertical,
+            StripeDirection::Diagonal,
+        ] {
+            let img = stripes(size, 3, direction, 0.5, white, black);
+            textures.push((format!("{direction:?} stripes"), img));
+        }
+        textures.push(("checkerboard".into(), checkerboard(size, 4, white, black)));
+        textures.push(("plasma".into(), plasma(size, 7)));
+
+        for (name, img) in textures {
+            let (inside, seams) = steps(&img);
+            assert!(inside > 0.0, "{name} is flat");
+            assert!(
+                seams <= inside * 1.05,
+                "{name}: {seams} at the seams, {inside} inside"
+            );
+        }
+    }
+
+    #[test]
+    fn checkerboards_wrap_onto_the_other_colour() {
+        let board = checkerboard((60, 40), 6, Rgba([255; 4]), Rgba([0, 0, 0, 255])).into_rgba8();
+        for y in 0..40 {
+            assert_ne!(board.get_pixel(0, y), board.get_pixel(59, y));
+        }
+        for x in 0..60 {
+            assert_ne!(board.get_pixel(x, 0), board.get_pixel(x, 39));
+        }
+    }
+}
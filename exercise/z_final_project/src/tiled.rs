@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::ops::Range;
+
+use image::{ColorType, DynamicImage, ImageBuffer, ImageFormat};
+
+use crate::registry::Shape;
+
+/// How to split an image into strips for `process_tiled`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TilePlan {
+    /// rows processed at once
+    pub rows_per_tile: u32,
+    /// extra rows above and below each strip that the operation can read but which aren't
+    /// written, e.g. the reach of a blur
+    pub overlap: u32,
+    /// rows of the input to keep in the output (all of them, unless cropping)
+    pub rows: Option<Range<u32>>,
+}
+
+/// Stream a PNG through `operation` a strip of rows at a time and write the result as a PNG,
+/// so only a few strips of a huge image are ever in memory.
+///
+/// Each strip is handed to `operation` with `plan.overlap` rows of context above and below,
+/// which are cut off again afterwards.  `operation` must keep the height of the strip, but may
+/// change its width or colour type.
+pub fn process_tiled(
+    infile: &str,
+    outfile: &str,
+    plan: &TilePlan,
+    operation: impl Fn(DynamicImage) -> Result<DynamicImage, String>,
+) -> Result<(), String> {
+    if ImageFormat::from_path(outfile).ok() != Some(ImageFormat::Png) {
+        return Err(format!("{outfile}: tiled output must be a PNG file"));
+    }
+    let mut reader = open_png(infile)?;
+    let (width, height) = reader.info().size();
+    let (colour_type, bit_depth) = reader.output_color_type();
+
+    let rows = plan.rows.clone().unwrap_or(0..height);
+    let rows = rows.start.min(height)..rows.end.min(height);
+    if rows.is_empty() {
+        return Err("the output would be empty".to_string());
+    }
+    let step = plan.rows_per_tile.max(1);
+
+    let mut buffered: VecDeque<Vec<u8>> = VecDeque::new();
+    let mut first_buffered = 0;
+    let mut writer = None;
+
+    for start in rows.clone().step_by(step as usize) {
+        let end = (start + step).min(rows.end);
+        let from = start.saturating_sub(plan.overlap);
+        let to = (end + plan.overlap).min(height);
+
+        // forget rows no strip needs any more and read the ones this strip does
+        while first_buffered < from {
+            if buffered.pop_front().is_none() {
+                skip_row(&mut reader, infile)?;
+            }
+            first_buffered += 1;
+        }
+        while first_buffered + (buffered.len() as u32) < to {
+            let row = reader
+                .next_row()
+                .map_err(|e| format!("{infile}: {e}"))?
+                .ok_or_else(|| format!("{infile}: the image ended early"))?;
+            buffered.push_back(row.data().to_vec());
+        }
+
+        let strip = strip_image(&buffered, width, colour_type, bit_depth)
+            .ok_or_else(|| format!("{infile}: unsupported PNG colour type {colour_type:?}"))?;
+        let processed = operation(strip)?;
+        let core = processed.crop_imm(0, start - from, processed.width(), end - start);
+
+        let stream = match &mut writer {
+            Some(stream) => stream,
+            None => writer.insert(start_png(outfile, &core, rows.len() as u32)?),
+        };
+        stream
+            .write_all(&png_bytes(&core)?)
+            .map_err(|e| format!("{outfile}: {e}"))?;
+    }
+
+    match writer {
+        Some(stream) => stream.finish().map_err(|e| format!("{outfile}: {e}")),
+        None => Ok(()),
+    }
+}
+
+/// The size and colour type of the PNG `infile` as `process_tiled` reads it, from its header.
+pub fn read_shape(infile: &str) -> Result<Shape, String> {
+    let reader = open_png(infile)?;
+    let (width, height) = reader.info().size();
+    let colour = match reader.output_color_type() {
+        (png::ColorType::Grayscale, png::BitDepth::Sixteen) => ColorType::L16,
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen) => ColorType::La16,
+        (png::ColorType::Rgb, png::BitDepth::Sixteen) => ColorType::Rgb16,
+        (png::ColorType::Rgba, png::BitDepth::Sixteen) => ColorType::Rgba16,
+        (png::ColorType::Grayscale, _) => ColorType::L8,
+        (png::ColorType::GrayscaleAlpha, _) => ColorType::La8,
+        (png::ColorType::Rgb, _) => ColorType::Rgb8,
+        (png::ColorType::Rgba, _) => ColorType::Rgba8,
+        (colour_type, _) => {
+            return Err(format!(
+                "{infile}: unsupported PNG colour type {colour_type:?}"
+            ))
+        }
+    };
+    Ok(Shape {
+        width,
+        height,
+        colour,
+    })
+}
+
+/// Start reading a PNG a row at a time, with palettes and low bit depths expanded.
+fn open_png(infile: &str) -> Result<png::Reader<BufReader<File>>, String> {
+    let file = File::open(infile).map_err(|e| format!("{infile}: {e}"))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let reader = decoder.read_info().map_err(|e| format!("{infile}: {e}"))?;
+    if reader.info().interlaced {
+        return Err(format!("{infile}: interlaced PNGs can't be read in tiles"));
+    }
+    Ok(reader)
+}
+
+/// Read and throw away a row that comes before the part of the image being kept.
+fn skip_row<R: std::io::Read>(reader: &mut png::Reader<R>, infile: &str) -> Result<(), String> {
+    reader
+        .next_row()
+        .map_err(|e| format!("{infile}: {e}"))?
+        .map(|_| ())
+        .ok_or_else(|| format!("{infile}: the image ended early"))
+}
+
+/// Build an image from decoded PNG rows.
+fn strip_image(
+    rows: &VecDeque<Vec<u8>>,
+    width: u32,
+    colour_type: png::ColorType,
+    bit_depth: png::BitDepth,
+) -> Option<DynamicImage> {
+    let height = rows.len() as u32;
+    let bytes: Vec<u8> = rows.iter().flatten().copied().collect();
+
+    if bit_depth == png::BitDepth::Sixteen {
+        let samples: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        return match colour_type {
+            png::ColorType::Grayscale => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma16)
+            }
+            png::ColorType::GrayscaleAlpha => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA16)
+            }
+            png::ColorType::Rgb => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16)
+            }
+            png::ColorType::Rgba => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16)
+            }
+            png::ColorType::Indexed => None,
+        };
+    }
+
+    match colour_type {
+        png::ColorType::Grayscale => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8)
+        }
+        png::ColorType::GrayscaleAlpha => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLumaA8)
+        }
+        png::ColorType::Rgb => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8)
+        }
+        png::ColorType::Rgba => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
+        }
+        png::ColorType::Indexed => None,
+    }
+}
+
+/// Write the PNG header for an image shaped like `first_strip` but `height` rows tall, and
+/// return a writer for its rows.
+fn start_png(
+    outfile: &str,
+    first_strip: &DynamicImage,
+    height: u32,
+) -> Result<png::StreamWriter<'static, BufWriter<File>>, String> {
+    let (colour_type, bit_depth) = match first_strip {
+        DynamicImage::ImageLuma8(_) => (png::ColorType::Grayscale, png::BitDepth::Eight),
+        DynamicImage::ImageLumaA8(_) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+        DynamicImage::ImageRgb8(_) => (png::ColorType::Rgb, png::BitDepth::Eight),
+        DynamicImage::ImageRgba8(_) => (png::ColorType::Rgba, png::BitDepth::Eight),
+        DynamicImage::ImageLuma16(_) => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+        DynamicImage::ImageLumaA16(_) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+        DynamicImage::ImageRgb16(_) => (png::ColorType::Rgb, png::BitDepth::Sixteen),
+        DynamicImage::ImageRgba16(_) => (png::ColorType::Rgba, png::BitDepth::Sixteen),
+        _ => return Err("tiled output must be 8 or 16-bit".to_string()),
+    };
+
+    let file = File::create(outfile).map_err(|e| format!("{outfile}: {e}"))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), first_strip.width(), height);
+    encoder.set_color(colour_type);
+    encoder.set_depth(bit_depth);
+    encoder
+        .write_header()
+        .and_then(|writer| writer.into_stream_writer())
+        .map_err(|e| format!("{outfile}: {e}"))
+}
+
+/// The raw PNG row data for an image: 16-bit samples are big endian.
+fn png_bytes(img: &DynamicImage) -> Result<Vec<u8>, String> {
+    let samples: Option<&[u16]> = match img {
+        DynamicImage::ImageLuma16(buffer) => Some(buffer),
+        DynamicImage::ImageLumaA16(buffer) => Some(buffer),
+        DynamicImage::ImageRgb16(buffer) => Some(buffer),
+        DynamicImage::ImageRgba16(buffer) => Some(buffer),
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            return Err("tiled output must be 8 or 16-bit".to_string())
+        }
+        _ => None,
+    };
+    Ok(match samples {
+        Some(samples) => samples.iter().flat_map(|s| s.to_be_bytes()).collect(),
+        None => img.as_bytes().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_pngs_are_tiled() {
+        let dir = std::env::temp_dir().join(format!("mirage-tiles-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let img = DynamicImage::new_rgb8(8, 8);
+        img.save(path("in.png")).unwrap();
+        img.save(path("in.jpg")).unwrap();
+        let plan = TilePlan {
+            rows_per_tile: 2,
+            overlap: 0,
+            rows: None,
+        };
+
+        assert_eq!(
+            process_tiled(&path("in.png"), &path("out.png"), &plan, Ok),
+            Ok(())
+        );
+        assert!(process_tiled(&path("in.png"), &path("out.jpg"), &plan, Ok).is_err());
+        assert!(process_tiled(&path("in.jpg"), &path("out.png"), &plan, Ok).is_err());
+        assert!(read_shape(&path("in.jpg")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
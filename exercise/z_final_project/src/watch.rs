@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use image::ImageFormat;
+
+/// Size and modification time of a file, which change while it is being written.
+type Stamp = (u64, SystemTime);
+
+/// Polls a folder for images that have been created or changed and have finished being
+/// written.
+pub struct Watcher {
+    dir: PathBuf,
+    /// stamps of the images in the folder at the last poll
+    previous: HashMap<PathBuf, Stamp>,
+    /// stamps of the images as they were when `poll` last returned them
+    reported: HashMap<PathBuf, Stamp>,
+}
+
+impl Watcher {
+    pub fn new(dir: &Path) -> Watcher {
+        Watcher {
+            dir: dir.to_path_buf(),
+            previous: HashMap::new(),
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Look at the folder again, returning the images that are new or changed since they were
+    /// last returned.
+    ///
+    /// An image counts as finished once its size and modification time are the same as at the
+    /// previous poll, so files are only returned on the second poll after they stop changing.
+    pub fn poll(&mut self) -> Result<Vec<PathBuf>, String> {
+        let dir = self.dir.display();
+        let mut current = HashMap::new();
+        let mut ready = Vec::new();
+
+        for entry in std::fs::read_dir(&self.dir).map_err(|e| format!("{dir}: {e}"))? {
+            let entry = entry.map_err(|e| format!("{dir}: {e}"))?;
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                // deleted since the folder was read
+                continue;
+            };
+            if !metadata.is_file() || ImageFormat::from_path(&path).is_err() {
+                continue;
+            }
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+
+            let stamp = (metadata.len(), modified);
+            if self.previous.get(&path) == Some(&stamp) && self.reported.get(&path) != Some(&stamp)
+            {
+                self.reported.insert(path.clone(), stamp);
+                ready.push(path.clone());
+            }
+            current.insert(path, stamp);
+        }
+
+        self.reported.retain(|path, _| current.contains_key(path));
+        self.previous = current;
+        ready.sort();
+        Ok(ready)
+    }
+}
+
+/// Remembers which image each result was written from, so that two images with the same name
+/// but different extensions (`a.png` and `a.tif`, converted to `a.jpg`) don't overwrite each
+/// other's results.
+#[derive(Default)]
+pub struct Outputs {
+    sources: HashMap<PathBuf, PathBuf>,
+}
+
+impl Outputs {
+    /// Claim `output` for the results of `input`.  If it already holds the results of another
+    /// image that is still there, that image is returned instead.
+    pub fn claim(&mut self, output: &Path, input: &Path) -> Result<(), PathBuf> {
+        match self.sources.get(output) {
+            Some(other) if other != input && other.exists() => Err(other.clone()),
+            _ => {
+                self.sources
+                    .insert(output.to_path_buf(), input.to_path_buf());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parse the time between polls, in seconds.
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.trim().parse().map_err(|e| format!("'{s}': {e}"))?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!(
+            "'{s}' should be a number of seconds, e.g. 2 or 0.5"
+        ));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("'{s}': {e}"))
+}
+
+/// Whether `output` was written after `input` last changed.
+pub fn up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_reported_once_they_stop_changing() {
+        let dir = std::env::temp_dir().join(format!("mirage-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("a.png");
+        std::fs::write(&image, b"half").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        let mut watcher = Watcher::new(&dir);
+        assert_eq!(watcher.poll(), Ok(vec![]));
+        assert_eq!(watcher.poll(), Ok(vec![image.clone()]));
+        assert_eq!(watcher.poll(), Ok(vec![]));
+
+        // still being written when the next poll comes round
+        std::fs::write(&image, b"half and the rest").unwrap();
+        assert_eq!(watcher.poll(), Ok(vec![]));
+        assert_eq!(watcher.poll(), Ok(vec![image.clone()]));
+        assert_eq!(watcher.poll(), Ok(vec![]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn intervals_must_be_finite() {
+        assert_eq!(parse_interval("0.5"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_interval("0"), Ok(Duration::ZERO));
+        for bad in ["inf", "NaN", "-1", "1e400", "soon"] {
+            assert!(parse_interval(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn outputs_are_claimed_by_one_image() {
+        let dir = std::env::temp_dir().join(format!("mirage-outputs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (png, tif) = (dir.join("a.png"), dir.join("a.tif"));
+        std::fs::write(&png, b"").unwrap();
+        std::fs::write(&tif, b"").unwrap();
+        let output = Path::new("out/a.jpg");
+
+        let mut outputs = Outputs::default();
+        assert_eq!(outputs.claim(output, &png), Ok(()));
+        assert_eq!(outputs.claim(output, &png), Ok(()));
+        assert_eq!(outputs.claim(output, &tif), Err(png.clone()));
+
+        // once the first image is gone, the name is free again
+        std::fs::remove_file(&png).unwrap();
+        assert_eq!(outputs.claim(output, &tif), Ok(()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}